@@ -0,0 +1,542 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::game::{GameState, Move, PlayConvention, Rules, Ruleset, Wythoff};
+
+/// Most positions the exhaustive search visits, and moves it lists, before giving up.
+const SEARCH_BUDGET: usize = 200_000;
+
+/// Most sticks a position can have for the exhaustive search to be tried, bigger ones
+/// can't be solved within the budget, and even listing their moves is slow.
+const MAX_SEARCH_STICKS: u64 = 500;
+
+/// Most moves the exhaustive search looks ahead, so it can't run out of stack.
+const MAX_SEARCH_DEPTH: usize = 500;
+
+/// Most positions whose outcome is remembered between searches, they are all forgotten
+/// once there are more.
+const MAX_KNOWN_POSITIONS: usize = 200_000;
+
+/// How long the computer waits before playing, so its moves can be followed on screen.
+const THINKING_TIME: Duration = Duration::from_millis(700);
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Random,
+    Easy,
+    Medium,
+    Perfect,
+}
+
+impl Difficulty {
+    /// Chance of playing the winning move when one is found, otherwise a random move is played.
+    pub fn winning_probability(&self) -> f64 {
+        match self {
+            Self::Random => 0.0,
+            Self::Easy => 0.3,
+            Self::Medium => 0.7,
+            Self::Perfect => 1.0,
+        }
+    }
+
+    pub fn choose_move(
+        &self,
+        game: &GameState,
+        analysis: &mut Analysis,
+        rng: &mut impl Rng,
+    ) -> Move {
+        let winning = if rng.gen_bool(self.winning_probability()) {
+            winning_move(&game.ruleset, &game.piles, game.convention, analysis)
+        } else {
+            None
+        };
+        winning.unwrap_or_else(|| random_move(game.ruleset.rules(), &game.piles, rng))
+    }
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Random => "Random",
+            Self::Easy => "Easy",
+            Self::Medium => "Medium",
+            Self::Perfect => "Perfect",
+        })
+    }
+}
+
+/// Computer opponent that plays through the same `GameState::pick` path as a human.
+#[derive(Default)]
+pub struct Bot {
+    thinking_since: Option<Instant>,
+    analysis: Analysis,
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plays a move if it is the bot's turn and it has finished "thinking",
+    /// returns true if the game state was changed.
+    pub fn on_tick(&mut self, game: &mut GameState) -> bool {
+        if game.is_game_over() || game.current_player == game.player_type {
+            self.thinking_since = None;
+            return false;
+        }
+
+        let thinking_since = *self.thinking_since.get_or_insert_with(Instant::now);
+        if thinking_since.elapsed() < THINKING_TIME {
+            return false;
+        }
+        self.thinking_since = None;
+
+        // Seeding from the game's seed and move count makes the bot's play reproducible.
+        let mut rng = StdRng::seed_from_u64(game.seed.wrapping_add(game.moves_played));
+        let difficulty = game.difficulty.unwrap_or(Difficulty::Perfect);
+        let mv = difficulty.choose_move(game, &mut self.analysis, &mut rng);
+        game.select(&mv);
+        game.pick();
+        true
+    }
+}
+
+/// XOR of all pile sizes, the position is lost for the player to move when it is zero.
+pub fn nim_sum(piles: &[u32]) -> u32 {
+    piles.iter().fold(0, |acc, pile| acc ^ pile)
+}
+
+/// What is known about positions of one game, kept between moves as it is slow to work out.
+#[derive(Default)]
+pub struct Analysis {
+    game: Option<(Ruleset, PlayConvention)>,
+    /// Grundy values of single piles, by size.
+    grundy: Vec<u32>,
+    /// Whether the player to move wins, by position with its non empty piles sorted,
+    /// as found by the exhaustive search.
+    known: HashMap<Vec<u32>, bool>,
+}
+
+impl Analysis {
+    /// Forgets everything known if it was about another game.
+    fn switch_to(&mut self, ruleset: &Ruleset, convention: PlayConvention) {
+        let game = (ruleset.clone(), convention);
+        if self.game.as_ref() != Some(&game) {
+            *self = Self {
+                game: Some(game),
+                ..Self::default()
+            };
+        }
+    }
+
+    /// Grundy values for piles of up to `max` sticks under the rules of the game,
+    /// only computed again when a larger pile is asked for.
+    fn grundy(&mut self, rules: &dyn Rules, max: u32) -> &[u32] {
+        if self.grundy.len() <= max as usize {
+            self.grundy = pile_grundy(rules, max);
+        }
+        &self.grundy
+    }
+}
+
+/// Returns a move that leaves the opponent in a lost position, if one is found.
+/// Misère positions too big to search are played as under normal play when Grundy values
+/// tell how, such a move may not win at all.
+pub fn winning_move(
+    ruleset: &Ruleset,
+    piles: &[u32],
+    convention: PlayConvention,
+    analysis: &mut Analysis,
+) -> Option<Move> {
+    analysis.switch_to(ruleset, convention);
+    match ruleset {
+        Ruleset::Nim => nim_winning_move(piles, convention),
+        Ruleset::Subtraction(_) | Ruleset::Lasker | Ruleset::Kayles => {
+            let rules = ruleset.rules();
+            if convention == PlayConvention::Misere {
+                if let Some(result) = search_winning_move(rules, piles, convention, analysis) {
+                    return result;
+                }
+            }
+            let max = piles.iter().copied().max().unwrap_or(0);
+            let grundy = analysis.grundy(rules, max);
+            grundy_winning_move(rules, piles, |size| grundy[size as usize])
+        }
+        Ruleset::Wythoff => match (convention, piles) {
+            (PlayConvention::Normal, [_, _]) => Wythoff.legal_moves(piles).into_iter().find(|mv| {
+                let mut after = piles.to_vec();
+                Wythoff.apply(&mut after, mv);
+                is_wythoff_p_position(after[0], after[1])
+            }),
+            _ => search_winning_move(&Wythoff, piles, convention, analysis).flatten(),
+        },
+    }
+}
+
+fn nim_winning_move(piles: &[u32], convention: PlayConvention) -> Option<Move> {
+    if convention == PlayConvention::Misere {
+        return misere_nim_winning_move(piles);
+    }
+
+    let sum = nim_sum(piles);
+    if sum == 0 {
+        return None;
+    }
+
+    piles.iter().enumerate().find_map(|(pile, &size)| {
+        let target = size ^ sum;
+        (target < size).then(|| Move::new(pile, size - target))
+    })
+}
+
+/// Misère play follows the normal strategy until it would leave only piles of one stick,
+/// from then on the goal is to leave an odd number of them.
+fn misere_nim_winning_move(piles: &[u32]) -> Option<Move> {
+    let ones = piles.iter().filter(|&&pile| pile == 1).count();
+    let mut large = (0..piles.len()).filter(|&i| piles[i] > 1);
+
+    match (large.next(), large.next()) {
+        (None, _) => {
+            // only single sticks left, taking one wins when their count is even
+            let pile = piles.iter().position(|&pile| pile == 1)?;
+            (ones % 2 == 0).then_some(Move::new(pile, 1))
+        }
+        (Some(pile), None) => {
+            let keep = if ones % 2 == 0 { 1 } else { 0 };
+            Some(Move::new(pile, piles[pile] - keep))
+        }
+        _ => nim_winning_move(piles, PlayConvention::Normal),
+    }
+}
+
+/// Losing positions for the player to move in Wythoff's game are the pairs
+/// (⌊kφ⌋, ⌊kφ²⌋), where φ is the golden ratio, so the difference between the
+/// piles is k and the smaller one is ⌊kφ⌋.
+pub fn is_wythoff_p_position(a: u32, b: u32) -> bool {
+    let golden_ratio = (1.0 + 5f64.sqrt()) / 2.0;
+    let (smaller, larger) = (a.min(b), a.max(b));
+    let k = (larger - smaller) as f64;
+    smaller as f64 == (k * golden_ratio).floor()
+}
+
+/// Grundy values of single piles of size `0..=max`, for rules where every move only
+/// touches one pile and leaves smaller piles behind.
+pub fn pile_grundy(rules: &dyn Rules, max: u32) -> Vec<u32> {
+    let mut grundy: Vec<u32> = Vec::with_capacity(max as usize + 1);
+    let mut after = Vec::with_capacity(2);
+    for size in 0..=max {
+        let moves = rules.legal_moves(&[size]);
+        // a pile has at most as many options as moves, so its value is at most that many
+        let mut reachable = vec![false; moves.len() + 1];
+        for mv in &moves {
+            after.clear();
+            after.push(size);
+            rules.apply(&mut after, mv);
+            let value = after
+                .iter()
+                .fold(0, |acc, &pile| acc ^ grundy[pile as usize]);
+            if let Some(seen) = reachable.get_mut(value as usize) {
+                *seen = true;
+            }
+        }
+        grundy.push(reachable.iter().position(|&seen| !seen).unwrap() as u32);
+    }
+    grundy
+}
+
+/// Plays to leave a position whose piles' Grundy values XOR to zero, which is what wins
+/// under normal play. Misère play has no such shortcut, it is searched instead.
+/// Moves are looked for one pile at a time, as splits give large piles a lot of them.
+fn grundy_winning_move(
+    rules: &dyn Rules,
+    piles: &[u32],
+    grundy: impl Fn(u32) -> u32,
+) -> Option<Move> {
+    let total = piles.iter().fold(0, |acc, &size| acc ^ grundy(size));
+    if total == 0 {
+        return None;
+    }
+
+    let mut after = Vec::with_capacity(2);
+    piles.iter().enumerate().find_map(|(pile, &size)| {
+        // what is left of the pile must be worth as much as all the other piles
+        let wanted = total ^ grundy(size);
+        let mv = rules.legal_moves(&[size]).into_iter().find(|mv| {
+            after.clear();
+            after.push(size);
+            rules.apply(&mut after, mv);
+            after.iter().fold(0, |acc, &size| acc ^ grundy(size)) == wanted
+        })?;
+        Some(Move { pile, ..mv })
+    })
+}
+
+/// Solves the position by exhaustive search, returns `None` if the search ran out of budget
+/// or went too deep, otherwise the winning move, if there is one. What is found is kept
+/// in `analysis` for the next searches.
+fn search_winning_move(
+    rules: &dyn Rules,
+    piles: &[u32],
+    convention: PlayConvention,
+    analysis: &mut Analysis,
+) -> Option<Option<Move>> {
+    let sticks: u64 = piles.iter().map(|&size| u64::from(size)).sum();
+    if sticks > MAX_SEARCH_STICKS {
+        return None;
+    }
+    if analysis.known.len() >= MAX_KNOWN_POSITIONS {
+        analysis.known.clear();
+    }
+    let mut search = Search {
+        rules,
+        convention,
+        known: &mut analysis.known,
+        budget: SEARCH_BUDGET,
+    };
+    for mv in rules.legal_moves(piles) {
+        let mut after = piles.to_vec();
+        rules.apply(&mut after, &mv);
+        if !search.wins(after, 1)? {
+            return Some(Some(mv));
+        }
+    }
+    Some(None)
+}
+
+struct Search<'a> {
+    rules: &'a dyn Rules,
+    convention: PlayConvention,
+    known: &'a mut HashMap<Vec<u32>, bool>,
+    /// Positions left to visit, and moves left to list, before giving up.
+    budget: usize,
+}
+
+impl Search<'_> {
+    /// Whether the player to move from `piles`, `depth` moves into the search, wins.
+    fn wins(&mut self, mut piles: Vec<u32>, depth: usize) -> Option<bool> {
+        if self.budget == 0 || depth > MAX_SEARCH_DEPTH {
+            return None;
+        }
+        self.budget -= 1;
+
+        // empty piles and the order of the piles never matter
+        piles.retain(|&pile| pile > 0);
+        piles.sort_unstable();
+        if let Some(&wins) = self.known.get(&piles) {
+            return Some(wins);
+        }
+
+        let moves = self.rules.legal_moves(&piles);
+        self.budget = self.budget.checked_sub(moves.len())?;
+        let mut wins = moves.is_empty() && self.convention == PlayConvention::Misere;
+        for mv in moves {
+            let mut after = piles.clone();
+            self.rules.apply(&mut after, &mv);
+            if !self.wins(after, depth + 1)? {
+                wins = true;
+                break;
+            }
+        }
+
+        self.known.insert(piles, wins);
+        Some(wins)
+    }
+}
+
+/// Returns a random legal move, the position must not be over.
+pub fn random_move(rules: &dyn Rules, piles: &[u32], rng: &mut impl Rng) -> Move {
+    let moves = rules.legal_moves(piles);
+    moves[rng.gen_range(0..moves.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Kayles, Lasker, Subtraction};
+
+    fn subtraction(set: &[u32]) -> Subtraction {
+        Subtraction { set: set.to_vec() }
+    }
+
+    #[test]
+    fn subtraction_grundy_values() {
+        let grundy = pile_grundy(&subtraction(&[1, 3, 4]), 7);
+        assert_eq!(grundy, [0, 1, 0, 1, 2, 3, 2, 0]);
+    }
+
+    #[test]
+    fn grundy_winning_move_leaves_zero() {
+        let rules = subtraction(&[1, 3, 4]);
+        let grundy = pile_grundy(&rules, 7);
+        let lookup = |size: u32| grundy[size as usize];
+
+        let mv = grundy_winning_move(&rules, &[5, 2], lookup).unwrap();
+        let mut after = vec![5, 2];
+        rules.apply(&mut after, &mv);
+        assert_eq!(after.iter().fold(0, |acc, &size| acc ^ lookup(size)), 0);
+
+        // 7 and 2 both have a Grundy value of 0, so every move loses
+        assert_eq!(grundy_winning_move(&rules, &[7, 2], lookup), None);
+    }
+
+    #[test]
+    fn kayles_grundy_values() {
+        assert_eq!(pile_grundy(&Kayles, 7), [0, 1, 2, 3, 1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn lasker_grundy_values() {
+        // piles of 4k + 3 and 4k + 4 swap values, as splits reach the other one
+        assert_eq!(pile_grundy(&Lasker, 8), [0, 1, 2, 4, 3, 5, 6, 8, 7]);
+    }
+
+    #[test]
+    fn analysis_follows_the_game() {
+        let mut analysis = Analysis::default();
+        analysis.switch_to(&Ruleset::Kayles, PlayConvention::Normal);
+        assert_eq!(analysis.grundy(&Kayles, 4), [0, 1, 2, 3, 1]);
+        // a smaller pile reuses the values already known
+        assert_eq!(analysis.grundy(&Kayles, 2).len(), 5);
+        assert_eq!(analysis.grundy(&Kayles, 7)[5..], [4, 3, 2]);
+
+        analysis.switch_to(&Ruleset::Lasker, PlayConvention::Normal);
+        assert!(analysis.grundy.is_empty());
+        assert_eq!(analysis.grundy(&Lasker, 4), [0, 1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn split_winning_move() {
+        // a Kayles row of 5 is won by knocking down the middle pin, leaving two rows of 2
+        let mut analysis = Analysis::default();
+        let mv = winning_move(
+            &Ruleset::Kayles,
+            &[5],
+            PlayConvention::Normal,
+            &mut analysis,
+        );
+        let mut after = vec![5];
+        Kayles.apply(&mut after, &mv.unwrap());
+        let values = analysis.grundy(&Kayles, 5);
+        assert_eq!(
+            after
+                .iter()
+                .fold(0, |acc, &size| acc ^ values[size as usize]),
+            0
+        );
+    }
+
+    #[test]
+    fn wythoff_p_positions() {
+        let p_positions: Vec<(u32, u32)> = (0..=7)
+            .flat_map(|a| (a..=7).map(move |b| (a, b)))
+            .filter(|&(a, b)| is_wythoff_p_position(a, b))
+            .collect();
+        assert_eq!(p_positions, [(0, 0), (1, 2), (3, 5), (4, 7)]);
+        assert!(is_wythoff_p_position(5, 3));
+    }
+
+    #[test]
+    fn wythoff_winning_move() {
+        let mut analysis = Analysis::default();
+        let mv = winning_move(
+            &Ruleset::Wythoff,
+            &[4, 6],
+            PlayConvention::Normal,
+            &mut analysis,
+        );
+        let mut after = vec![4, 6];
+        Wythoff.apply(&mut after, &mv.unwrap());
+        assert!(is_wythoff_p_position(after[0], after[1]));
+
+        let mv = winning_move(
+            &Ruleset::Wythoff,
+            &[3, 5],
+            PlayConvention::Normal,
+            &mut analysis,
+        );
+        assert_eq!(mv, None);
+    }
+
+    #[test]
+    fn misere_search() {
+        // taking the last stick loses, so piles of 3k + 1 are lost for the player to move
+        let ruleset = Ruleset::Subtraction(subtraction(&[1, 2]));
+        let mut analysis = Analysis::default();
+        let mut best =
+            |piles: &[u32]| winning_move(&ruleset, piles, PlayConvention::Misere, &mut analysis);
+
+        assert_eq!(best(&[5]), Some(Move::new(0, 1)));
+        assert_eq!(best(&[1]), None);
+        assert_eq!(best(&[4]), None);
+        assert_eq!(best(&[2, 2]), None);
+    }
+
+    #[test]
+    fn misere_search_is_kept_between_moves() {
+        let ruleset = Ruleset::Subtraction(subtraction(&[1, 2]));
+        let mut analysis = Analysis::default();
+        winning_move(&ruleset, &[4, 5], PlayConvention::Misere, &mut analysis);
+        assert_eq!(analysis.known.get(&vec![4]), Some(&false));
+
+        analysis.switch_to(&ruleset, PlayConvention::Normal);
+        assert!(analysis.known.is_empty());
+    }
+
+    #[test]
+    fn misere_search_gives_up_on_big_games() {
+        let rules = subtraction(&[1]);
+        let mut analysis = Analysis::default();
+        analysis.switch_to(&Ruleset::Subtraction(rules.clone()), PlayConvention::Misere);
+        let piles = [1000; 100];
+        let result = search_winning_move(&rules, &piles, PlayConvention::Misere, &mut analysis);
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn misere_search_gives_up_on_wide_games() {
+        let mut analysis = Analysis::default();
+        analysis.switch_to(&Ruleset::Kayles, PlayConvention::Misere);
+        let piles = [20; 10];
+        let result = search_winning_move(&Kayles, &piles, PlayConvention::Misere, &mut analysis);
+        assert_eq!(result, None);
+        assert!(analysis.known.len() <= SEARCH_BUDGET);
+    }
+
+    #[test]
+    fn misere_nim_with_one_large_pile() {
+        // an even number of single sticks: leave one more, so the opponent faces an odd number
+        assert_eq!(misere_nim_winning_move(&[1, 1, 5]), Some(Move::new(2, 4)));
+        assert_eq!(misere_nim_winning_move(&[5]), Some(Move::new(0, 4)));
+        // an odd number already: empty the large pile
+        assert_eq!(misere_nim_winning_move(&[1, 5]), Some(Move::new(1, 5)));
+        assert_eq!(
+            misere_nim_winning_move(&[1, 0, 1, 1, 2]),
+            Some(Move::new(4, 2))
+        );
+    }
+
+    #[test]
+    fn misere_nim_with_only_single_sticks() {
+        assert_eq!(misere_nim_winning_move(&[0, 1, 1]), Some(Move::new(1, 1)));
+        assert_eq!(misere_nim_winning_move(&[1, 1, 1]), None);
+        assert_eq!(misere_nim_winning_move(&[1]), None);
+    }
+
+    #[test]
+    fn misere_nim_plays_like_normal_nim_with_several_large_piles() {
+        for piles in [[2, 3, 4], [1, 2, 3], [1, 4, 5], [2, 2, 1]] {
+            assert_eq!(
+                misere_nim_winning_move(&piles),
+                nim_winning_move(&piles, PlayConvention::Normal)
+            );
+        }
+        assert_eq!(misere_nim_winning_move(&[2, 3, 4]), Some(Move::new(2, 3)));
+        assert_eq!(misere_nim_winning_move(&[1, 2, 3]), None);
+    }
+}
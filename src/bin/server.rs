@@ -0,0 +1,24 @@
+use std::{env, net::SocketAddr, process, sync::Arc};
+
+use term_nim::comms::lobby::{Lobby, LOBBY_PORT};
+
+/// Hosts a lobby any number of games can be played through,
+/// listening on the address given as the first argument.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| format!("0.0.0.0:{LOBBY_PORT}"));
+    let Ok(addr) = addr.parse::<SocketAddr>() else {
+        eprintln!("Invalid address: {addr}");
+        process::exit(1);
+    };
+
+    tracing::info!("lobby listening on {addr}");
+    if let Err(e) = Arc::new(Lobby::default()).serve(addr).await {
+        eprintln!("Lobby stopped: {e}");
+        process::exit(1);
+    }
+}
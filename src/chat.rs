@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use crossterm::event::KeyCode;
+use serde::{Deserialize, Serialize};
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::{
+    game::MAX_NAME_LENGTH,
+    ui::{form::StringForm, utils::get_last_rows_for_rect},
+};
+
+/// How many lines are kept, older ones are dropped.
+const SCROLLBACK: usize = 100;
+
+/// Longest line that can be sent.
+pub const MAX_LINE_LENGTH: usize = 200;
+
+/// Width the chat panel takes next to the piles.
+pub const PANEL_WIDTH: u16 = 32;
+
+/// Something said by a player.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ChatLine {
+    pub from: String,
+    pub text: String,
+}
+
+impl ChatLine {
+    /// A line said by `from`, both cut down to their longest allowed length.
+    pub fn new(from: &str, text: &str) -> Self {
+        Self {
+            from: from.chars().take(MAX_NAME_LENGTH).collect(),
+            text: text.chars().take(MAX_LINE_LENGTH).collect(),
+        }
+    }
+}
+
+/// What a key did to the chat.
+pub enum ChatInput {
+    /// The key was used by the chat, and shouldn't reach the game.
+    Handled,
+    /// A line was typed in, to be sent.
+    Send(String),
+}
+
+/// Lines exchanged during a networked game, shown in a panel next to the piles.
+#[derive(Clone)]
+pub struct Chat {
+    lines: VecDeque<ChatLine>,
+    open: bool,
+    /// Lines received while the panel was closed.
+    unread: usize,
+    /// Spectators can read the chat but not talk.
+    read_only: bool,
+    input: StringForm,
+}
+
+impl Chat {
+    pub fn new(read_only: bool) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            open: false,
+            unread: 0,
+            read_only,
+            input: StringForm::new("Enter to send".into(), PANEL_WIDTH, None),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    pub fn push(&mut self, line: ChatLine) {
+        if self.lines.len() == SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        if !self.open {
+            self.unread += 1;
+        }
+    }
+
+    /// C opens the panel, then what is typed goes to the input until Enter sends it.
+    /// Enter with nothing typed closes the panel again.
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<ChatInput> {
+        if !self.open {
+            if let KeyCode::Char('c' | 'C') = key {
+                self.open = true;
+                self.unread = 0;
+                return Some(ChatInput::Handled);
+            }
+            return None;
+        }
+
+        match key {
+            KeyCode::Enter if self.read_only || self.input.state.is_empty() => {
+                self.open = false;
+                Some(ChatInput::Handled)
+            }
+            KeyCode::Enter => {
+                let text = self.input.consume();
+                self.input.state.clear();
+                Some(ChatInput::Send(text))
+            }
+            KeyCode::Char(_) | KeyCode::Backspace if !self.read_only => {
+                self.input.handle_key(key);
+                Some(ChatInput::Handled)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn render<B: Backend>(&self, frame: &mut Frame<B>, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title("Chat");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let (history, input) = if self.read_only {
+            (inner, None)
+        } else {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(7)])
+                .split(inner);
+            (chunks[0], Some(chunks[1]))
+        };
+
+        // lines are wrapped by hand, so the newest ones can be kept at the bottom
+        let lines = self
+            .lines
+            .iter()
+            .map(|line| format!("{}: {}", line.from, line.text));
+        let rows = get_last_rows_for_rect(&history, lines);
+        frame.render_widget(Paragraph::new(rows), history);
+
+        if let Some(input) = input {
+            self.input.render_in(frame, input);
+        }
+    }
+}
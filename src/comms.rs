@@ -1,39 +1,582 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::tungstenite;
+
+use crate::{
+    chat::ChatLine,
+    game::{Clock, GameState, Move, Ruleset},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(Box<tungstenite::Error>),
+    Http(reqwest::Error),
+    /// The peer sent something that isn't a valid game.
+    InvalidGame(String),
+    /// The connection to the peer was closed.
+    Closed,
+    /// Nothing, not even a heartbeat, was heard from the peer for too long.
+    TimedOut,
+    /// The host turned us down during the handshake, with why.
+    Refused(String),
+    /// The peer runs a version we can't play with.
+    Incompatible(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSocket(e) => write!(f, "{e}"),
+            Self::Http(e) => write!(f, "{e}"),
+            Self::InvalidGame(e) => write!(f, "invalid game: {e}"),
+            Self::Closed => f.write_str("connection closed"),
+            Self::TimedOut => f.write_str("connection timed out"),
+            Self::Refused(reason) => write!(f, "refused by the host: {reason}"),
+            Self::Incompatible(reason) => write!(f, "incompatible versions: {reason}"),
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Self::WebSocket(Box::new(e))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+/// How often a heartbeat is sent.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a peer can stay silent before the connection is considered lost.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Version of the messages exchanged by peers, bumped whenever their format changes.
+pub const PROTOCOL_VERSION: u32 = 10;
+
+/// Introduction exchanged when a guest connects, before any game is sent.
+/// Its format must never change, so older and newer versions can still refuse each other.
+#[derive(Deserialize, Serialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub app_version: String,
+    /// Variants this version can play, see `Ruleset::NAMES`.
+    pub variants: Vec<String>,
+    pub player_name: String,
+}
+
+impl Hello {
+    pub fn new() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").into(),
+            variants: Ruleset::NAMES.iter().map(|name| name.to_string()).collect(),
+            player_name: player_name(),
+        }
+    }
+
+    /// Checks we can play with `peer`, `ruleset` being the game to be played, when known.
+    pub fn check(&self, peer: &Hello, ruleset: Option<&Ruleset>) -> Result<(), String> {
+        if peer.protocol_version != self.protocol_version {
+            return Err(format!(
+                "{} runs term-nim {} (protocol {}), which can't play with term-nim {} (protocol {})",
+                peer.player_name,
+                peer.app_version,
+                peer.protocol_version,
+                self.app_version,
+                self.protocol_version
+            ));
+        }
+        if let Some(ruleset) = ruleset {
+            if !peer.variants.contains(&ruleset.to_string()) {
+                return Err(format!("{} can't play {ruleset}", peer.player_name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The host's answer to a guest's `Hello`.
+#[derive(Deserialize, Serialize)]
+pub enum Handshake {
+    Accepted(Hello),
+    Refused(String),
+}
+
+/// Name the local player is shown with to others, taken from the user running the game.
+pub fn player_name() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "Anonymous".into())
+}
+
+/// Everything the two players send each other once connected.
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize, Serialize)]
+pub enum Message {
+    /// The whole game as the receiver sees it, sent by the host when the guest joins and
+    /// whenever the guest's copy has to be corrected.
+    Game(GameState),
+    Move(Move),
+    /// The last move sent was refused, with the reason.
+    Rejected(String),
+    /// How many spectators are watching, sent by the host whenever it changes.
+    Spectators(usize),
+    /// Sent by a lobby to whoever created a game, with the ID others can join it by.
+    GameCreated(u32),
+    /// Sent by a lobby whenever the opponent takes or leaves their seat.
+    OpponentPresent(bool),
+    /// Asks for, or accepts, a rematch once the game is over.
+    /// The host, or the lobby, starts it by sending the new game once both sides agree.
+    Rematch,
+    /// The sender left the game for good, it won't reconnect.
+    Left,
+    /// A line said in the chat, passed on by the host to the spectators.
+    Chat(ChatLine),
+    /// Time both players have left, sent by the host after every move.
+    Clock(Clock),
+    /// Sent regularly so a peer that vanished without closing the connection is noticed.
+    Heartbeat,
+    /// Sent to a player taking a seat, before the game, for them to present when reconnecting.
+    Seat(SeatToken),
+}
+
+/// Secret a player is given with their seat, which is kept for them while they're gone
+/// and only given back to whoever presents it.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct SeatToken(u64);
+
+impl SeatToken {
+    pub fn new() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl Default for SeatToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a player reconnecting to their game presents, as the query of the URL they connect to.
+#[derive(Deserialize, Serialize)]
+pub struct Rejoin {
+    pub seat: Option<SeatToken>,
+}
+
+/// What a peer connecting to a host joins its game as.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JoinAs {
+    Player,
+    /// Only watches, receiving the game after every move.
+    Spectator,
+}
+
+impl JoinAs {
+    /// Endpoint of the host's server to connect to.
+    fn path(&self) -> &'static str {
+        match self {
+            Self::Player => "connect",
+            Self::Spectator => "watch",
+        }
+    }
+}
+
+/// Sending half of a connection, for when messages must reach it from elsewhere.
+#[derive(Clone)]
+pub struct Outbox(UnboundedSender<Message>);
+
+impl Outbox {
+    pub fn send(&self, message: Message) -> Result<(), Error> {
+        self.0.send(message).map_err(|_| Error::Closed)
+    }
+
+    /// Whether both send to the same connection.
+    pub fn same_connection(&self, other: &Outbox) -> bool {
+        self.0.same_channel(&other.0)
+    }
+}
+
+/// A persistent connection to the other player, messages are pushed both ways
+/// and read from a queue so the UI never waits on the network.
+pub struct Connection {
+    sender: UnboundedSender<Message>,
+    receiver: UnboundedReceiver<Message>,
+    last_heard: Instant,
+}
+
+impl Connection {
+    /// Moves the socket into background tasks that write outgoing messages and queue incoming ones,
+    /// `text` and `read_text` convert between JSON and the socket's own message type.
+    fn spawn<S, M, E>(socket: S, text: fn(String) -> M, read_text: fn(M) -> Option<String>) -> Self
+    where
+        S: Stream<Item = Result<M, E>> + Sink<M> + Send + 'static,
+        M: Send + Unpin + 'static,
+    {
+        let (mut sink, mut stream) = socket.split();
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Message>();
+        let (incoming, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                let message = tokio::select! {
+                    message = outgoing.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = heartbeat.tick() => Message::Heartbeat,
+                };
+                let json = serde_json::to_string(&message).unwrap();
+                if sink.send(text(json)).await.is_err() {
+                    return;
+                }
+            }
+            // the connection was dropped on our side
+            let _ = sink.close().await;
+        });
+
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                // anything that isn't one of our messages is ignored
+                let message = read_text(message).and_then(|text| serde_json::from_str(&text).ok());
+                let Some(message) = message else {
+                    continue;
+                };
+                if incoming.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            sender,
+            receiver,
+            last_heard: Instant::now(),
+        }
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), Error> {
+        self.sender.send(message).map_err(|_| Error::Closed)
+    }
+
+    pub fn outbox(&self) -> Outbox {
+        Outbox(self.sender.clone())
+    }
+
+    /// Returns the next message received, if any, without waiting.
+    /// Fails once the peer is gone, closed or silent.
+    pub fn try_recv(&mut self) -> Result<Option<Message>, Error> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => {
+                    self.last_heard = Instant::now();
+                    if !matches!(message, Message::Heartbeat) {
+                        return Ok(Some(message));
+                    }
+                }
+                Err(TryRecvError::Empty) if self.last_heard.elapsed() > HEARTBEAT_TIMEOUT => {
+                    return Err(Error::TimedOut)
+                }
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(Error::Closed),
+            }
+        }
+    }
+
+    /// Waits for the next message, failing once the peer is gone, closed or silent.
+    pub async fn recv(&mut self) -> Result<Message, Error> {
+        loop {
+            let deadline = (self.last_heard + HEARTBEAT_TIMEOUT).into();
+            match tokio::time::timeout_at(deadline, self.receiver.recv()).await {
+                Ok(Some(Message::Heartbeat)) => self.last_heard = Instant::now(),
+                Ok(Some(message)) => {
+                    self.last_heard = Instant::now();
+                    return Ok(message);
+                }
+                Ok(None) => return Err(Error::Closed),
+                Err(_) => return Err(Error::TimedOut),
+            }
+        }
+    }
+}
+
 pub mod client {
+    use std::time::Duration;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite;
+
+    use super::{lobby::Listing, Connection, Error, Handshake, Hello, JoinAs, Message, SeatToken};
     use crate::game::GameState;
 
-    #[derive(Clone)]
-    pub struct Client {
-        url: String,
-        client: reqwest::Client,
+    /// Joins the game hosted at `addr` after introducing ourselves,
+    /// returning it as seen by the guest, or by the host when spectating,
+    /// and the token to take our seat back with.
+    /// `seat` is the token of the seat we had, when reconnecting.
+    /// Games in a lobby are at `{lobby address}/games/{id}`.
+    pub async fn connect(
+        addr: &str,
+        join_as: JoinAs,
+        seat: Option<SeatToken>,
+    ) -> Result<(GameState, Connection, Option<SeatToken>), Error> {
+        let mut url = format!("ws://{addr}/{}", join_as.path());
+        if let Some(SeatToken(token)) = seat {
+            url += &format!("?seat={token}");
+        }
+        let mut connection = open(&url).await?;
+
+        let mut seat = None;
+        loop {
+            match connection.recv().await? {
+                Message::Seat(token) => seat = Some(token),
+                Message::Game(mut game_state) => {
+                    game_state.validate().map_err(Error::InvalidGame)?;
+                    game_state.spectating = join_as == JoinAs::Spectator;
+                    return Ok((game_state, connection, seat));
+                }
+                Message::Rejected(reason) => return Err(Error::Refused(reason)),
+                _ => return Err(Error::InvalidGame("the host did not send the game".into())),
+            }
+        }
+    }
+
+    /// Opens a game in the lobby at `addr`, returning its ID, the connection to play it
+    /// through, from the host's seat, and the token to take that seat back with.
+    pub async fn create_game(
+        addr: &str,
+        game_state: &GameState,
+    ) -> Result<(u32, Connection, Option<SeatToken>), Error> {
+        let mut connection = open(&format!("ws://{addr}/create")).await?;
+        connection.send(Message::Game(game_state.clone()))?;
+        let mut seat = None;
+        loop {
+            match connection.recv().await? {
+                Message::Seat(token) => seat = Some(token),
+                Message::GameCreated(id) => return Ok((id, connection, seat)),
+                Message::Rejected(reason) => return Err(Error::Refused(reason)),
+                _ => return Err(Error::InvalidGame("the lobby did not open the game".into())),
+            }
+        }
+    }
+
+    /// How long listing a lobby's games may take before giving up.
+    const LIST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Lists the games open in the lobby at `addr`.
+    pub async fn list_games(addr: &str) -> Result<Vec<Listing>, Error> {
+        let games = reqwest::Client::builder()
+            .timeout(LIST_TIMEOUT)
+            .build()?
+            .get(format!("http://{addr}/games"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(games)
+    }
+
+    /// Opens a connection to `url`, introducing ourselves before anything else is sent.
+    async fn open(url: &str) -> Result<Connection, Error> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+
+        let hello = Hello::new();
+        let json = serde_json::to_string(&hello).unwrap();
+        socket.send(tungstenite::Message::Text(json)).await?;
+        let reply = loop {
+            match socket.next().await.ok_or(Error::Closed)?? {
+                tungstenite::Message::Text(text) => break text,
+                _ => continue,
+            }
+        };
+        match serde_json::from_str(&reply) {
+            Ok(Handshake::Accepted(host)) => {
+                hello.check(&host, None).map_err(Error::Incompatible)?
+            }
+            Ok(Handshake::Refused(reason)) => return Err(Error::Refused(reason)),
+            Err(_) => {
+                return Err(Error::Incompatible(
+                    "the host's version of term-nim can't be understood".into(),
+                ))
+            }
+        }
+
+        Ok(Connection::spawn(
+            socket,
+            tungstenite::Message::Text,
+            |message| match message {
+                tungstenite::Message::Text(text) => Some(text),
+                _ => None,
+            },
+        ))
+    }
+}
+
+/// Finding games on the local network: hosts broadcast an announcement every second
+/// and guests list the ones heard from recently.
+pub mod discovery {
+    use std::{
+        collections::HashMap,
+        fmt::{self, Display},
+        io,
+        net::{Ipv4Addr, SocketAddr},
+        time::{Duration, Instant},
+    };
+
+    use serde::{Deserialize, Serialize};
+    use tokio::{
+        net::UdpSocket,
+        sync::mpsc::{self, UnboundedReceiver},
+        task::JoinHandle,
+    };
+
+    use crate::game::{summarize_piles, GameState, PlayConvention, Ruleset};
+
+    pub const DISCOVERY_PORT: u16 = 4089;
+    const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+    /// Games that haven't been announced for this long are considered gone.
+    const ANNOUNCEMENT_TIMEOUT: Duration = Duration::from_secs(3);
+    /// Room for the largest UDP datagram, so announcements of big custom games aren't cut off.
+    const MAX_ANNOUNCEMENT_SIZE: usize = 64 * 1024;
+
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+    pub struct Announcement {
+        pub host_name: String,
+        /// Address the game's server listens on.
+        pub addr: String,
+        pub piles: Vec<u32>,
+        pub ruleset: Ruleset,
+        pub convention: PlayConvention,
     }
 
-    impl Client {
-        pub fn new(url: String) -> Self {
+    impl Announcement {
+        pub fn new(addr: String, game: &GameState) -> Self {
             Self {
-                url: format!("http://{url}"),
-                client: reqwest::Client::new(),
+                host_name: super::player_name(),
+                addr,
+                piles: game.piles.clone(),
+                ruleset: game.ruleset.clone(),
+                convention: game.convention,
             }
         }
+    }
 
-        pub async fn check_connection(&self) -> Result<reqwest::Response, reqwest::Error> {
-            self.client.get(&self.url).send().await
+    impl Display for Announcement {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let piles = summarize_piles(&self.piles);
+            write!(
+                f,
+                "{} - {} ({}) - {piles} - {}",
+                self.host_name, self.ruleset, self.convention, self.addr
+            )
         }
+    }
+
+    /// Broadcasts a game until dropped.
+    pub struct Announcer(JoinHandle<()>);
 
-        pub async fn connect_to_game(
-            &self,
-            guest_url: String,
-        ) -> Result<GameState, reqwest::Error> {
-            let url = format!("{}/connect", self.url);
-            let res = self.client.post(&url).json(&guest_url).send().await?;
-            res.json().await
+    impl Announcer {
+        pub fn start(announcement: Announcement) -> Self {
+            Self(tokio::spawn(async move {
+                let Ok(socket) = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await else {
+                    return;
+                };
+                let _ = socket.set_broadcast(true);
+                let packet = serde_json::to_vec(&announcement).unwrap();
+                loop {
+                    // loopback is announced to as well, so games can be found on the same machine
+                    for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+                        let _ = socket.send_to(&packet, (ip, DISCOVERY_PORT)).await;
+                    }
+                    tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+                }
+            }))
         }
+    }
 
-        pub async fn send_game_state(&self, game_state: &GameState) -> Result<(), reqwest::Error> {
-            let url = format!("{}/game", self.url);
-            let mut game_state = game_state.clone();
-            game_state.player_type.flip();
-            self.client.post(&url).json(&game_state).send().await?;
-            Ok(())
+    impl Drop for Announcer {
+        fn drop(&mut self) {
+            self.0.abort();
+        }
+    }
+
+    /// Listens for games announced on the local network until dropped.
+    pub struct Browser {
+        receiver: UnboundedReceiver<Announcement>,
+        /// Games heard from, by address, with when they were last announced.
+        games: HashMap<String, (Announcement, Instant)>,
+        task: JoinHandle<()>,
+    }
+
+    impl Browser {
+        pub async fn start() -> io::Result<Self> {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).await?;
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = tokio::spawn(async move {
+                let mut buf = vec![0; MAX_ANNOUNCEMENT_SIZE];
+                while let Ok((len, source)) = socket.recv_from(&mut buf).await {
+                    let Ok(mut announcement) = serde_json::from_slice::<Announcement>(&buf[..len])
+                    else {
+                        continue;
+                    };
+                    // a host listening on every interface is reached through the one it announced from
+                    if let Ok(addr) = announcement.addr.parse::<SocketAddr>() {
+                        if addr.ip().is_unspecified() {
+                            announcement.addr =
+                                SocketAddr::new(source.ip(), addr.port()).to_string();
+                        }
+                    }
+                    if sender.send(announcement).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            Ok(Self {
+                receiver,
+                games: HashMap::new(),
+                task,
+            })
+        }
+
+        /// Returns the games announced recently, sorted by host.
+        pub fn games(&mut self) -> Vec<Announcement> {
+            while let Ok(announcement) = self.receiver.try_recv() {
+                self.games
+                    .insert(announcement.addr.clone(), (announcement, Instant::now()));
+            }
+            self.games
+                .retain(|_, (_, seen)| seen.elapsed() < ANNOUNCEMENT_TIMEOUT);
+
+            let mut games: Vec<Announcement> =
+                self.games.values().map(|(game, _)| game.clone()).collect();
+            games.sort_by(|a, b| (&a.host_name, &a.addr).cmp(&(&b.host_name, &b.addr)));
+            games
+        }
+    }
+
+    impl Drop for Browser {
+        fn drop(&mut self) {
+            self.task.abort();
         }
     }
 }
@@ -41,26 +584,44 @@ pub mod client {
 pub mod server {
     use std::{
         collections::VecDeque,
+        net::SocketAddr,
         sync::{Arc, Mutex},
     };
 
     use axum::{
-        extract::State,
-        routing::{get, post},
-        Json, Router,
+        extract::{
+            ws::{self, WebSocket},
+            Query, State, WebSocketUpgrade,
+        },
+        response::Response,
+        routing::get,
+        Router,
     };
+    use tokio::sync::oneshot;
 
-    use crate::game::GameState;
+    use super::{
+        discovery::{Announcement, Announcer},
+        Connection, Handshake, Hello, JoinAs, Message, Rejoin, SeatToken,
+    };
+    use crate::game::{GameState, PlayerType, Ruleset};
 
     pub struct Server {
         pub url: String,
         pub messages: Mutex<VecDeque<ServerMessage>>,
         pub current_game_state: Arc<Mutex<GameState>>,
+        /// Announces the game on the local network until a guest joins.
+        announcer: Mutex<Option<Announcer>>,
+        /// Tells the server to stop listening, once started.
+        shutdown: Mutex<Option<oneshot::Sender<()>>>,
+        /// Token of the guest's seat once taken, only whoever presents it can join as a player.
+        seat: Mutex<Option<SeatToken>>,
     }
 
     pub enum ServerMessage {
-        UpdatedGameState,
-        GuestConnected(String),
+        GuestConnected(Connection),
+        SpectatorJoined(Connection),
+        /// A guest or spectator failed the handshake, with why.
+        JoinRefused(String),
     }
 
     impl Server {
@@ -69,19 +630,50 @@ pub mod server {
                 url,
                 messages: Mutex::new(VecDeque::new()),
                 current_game_state: state,
+                announcer: Mutex::new(None),
+                shutdown: Mutex::new(None),
+                seat: Mutex::new(None),
             }
         }
 
-        pub async fn start(self: Arc<Self>) {
+        /// Starts announcing the game on the local network.
+        pub fn announce(&self) {
+            let announcement =
+                Announcement::new(self.url.clone(), &self.current_game_state.lock().unwrap());
+            *self.announcer.lock().unwrap() = Some(Announcer::start(announcement));
+        }
+
+        /// Starts listening on `url`, serving in the background.
+        pub fn start(self: &Arc<Self>) -> Result<(), String> {
+            let addr: SocketAddr = self
+                .url
+                .parse()
+                .map_err(|_| format!("Invalid address: {}", self.url))?;
+            let builder = axum::Server::try_bind(&addr).map_err(|e| e.to_string())?;
             let app = Router::new()
                 .route("/", get(root))
-                .route("/connect", post(connect))
-                .route("/game", post(game))
+                .route("/connect", get(connect))
+                .route("/watch", get(watch))
                 .with_state(self.clone());
-            axum::Server::bind(&self.url.parse().unwrap())
-                .serve(app.into_make_service())
-                .await
-                .unwrap();
+            let (shutdown, stopped) = oneshot::channel();
+            *self.shutdown.lock().unwrap() = Some(shutdown);
+            tokio::spawn(
+                builder
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(async {
+                        stopped.await.ok();
+                    }),
+            );
+            Ok(())
+        }
+
+        /// Stops announcing the game and listening, freeing the address for the next game.
+        /// Players already connected are kept.
+        pub fn stop(&self) {
+            self.announcer.lock().unwrap().take();
+            if let Some(shutdown) = self.shutdown.lock().unwrap().take() {
+                let _ = shutdown.send(());
+            }
         }
     }
 
@@ -91,28 +683,567 @@ pub mod server {
 
     async fn connect(
         State(server): State<Arc<Server>>,
-        Json(guest_url): Json<String>,
-    ) -> Json<GameState> {
-        server
-            .messages
-            .lock()
-            .unwrap()
-            .push_back(ServerMessage::GuestConnected(guest_url));
-        let mut game_state = server.current_game_state.lock().unwrap().clone();
-        game_state.player_type.flip();
-        Json(game_state)
-    }
-
-    async fn game(
-        State(server): State<Arc<Server>>,
-        Json(game_state): Json<GameState>,
-    ) -> &'static str {
-        *server.current_game_state.lock().unwrap() = game_state;
-        server
-            .messages
-            .lock()
-            .unwrap()
-            .push_back(ServerMessage::UpdatedGameState);
-        "OK"
+        Query(rejoin): Query<Rejoin>,
+        upgrade: WebSocketUpgrade,
+    ) -> Response {
+        upgrade.on_upgrade(move |socket| join(server, socket, JoinAs::Player, rejoin.seat))
+    }
+
+    async fn watch(State(server): State<Arc<Server>>, upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(move |socket| join(server, socket, JoinAs::Spectator, None))
+    }
+
+    /// Answers the `Hello` a peer starts with and, if we can play together,
+    /// sends it the game to start from. Once the guest's seat is taken,
+    /// only a player presenting its token as `seat` can join as a player.
+    async fn join(
+        server: Arc<Server>,
+        mut socket: WebSocket,
+        join_as: JoinAs,
+        seat: Option<SeatToken>,
+    ) {
+        let ruleset = server.current_game_state.lock().unwrap().ruleset.clone();
+        let peer = match answer_hello(&mut socket, Some(&ruleset)).await {
+            Some(Ok(peer)) => peer,
+            Some(Err(reason)) => {
+                server
+                    .messages
+                    .lock()
+                    .unwrap()
+                    .push_back(ServerMessage::JoinRefused(reason));
+                return;
+            }
+            None => return,
+        };
+
+        let connection = accept(socket);
+        // the seat is held on to until the guest is in, so two players can't both take it
+        let mut taken = server.seat.lock().unwrap();
+        let token = match (join_as, *taken) {
+            (JoinAs::Spectator, _) => None,
+            (JoinAs::Player, Some(token)) if seat != Some(token) => {
+                let _ = connection.send(Message::Rejected("the game is full".into()));
+                let reason = format!("{} can't take the guest's seat", peer.player_name);
+                server
+                    .messages
+                    .lock()
+                    .unwrap()
+                    .push_back(ServerMessage::JoinRefused(reason));
+                return;
+            }
+            (JoinAs::Player, taken) => Some(taken.unwrap_or_default()),
+        };
+        if let Some(token) = token {
+            if connection.send(Message::Seat(token)).is_err() {
+                return;
+            }
+        }
+        let game_state = {
+            let mut game_state = server.current_game_state.lock().unwrap();
+            match join_as {
+                JoinAs::Player => {
+                    game_state.names.set(&PlayerType::Guest, &peer.player_name);
+                    game_state.for_opponent()
+                }
+                JoinAs::Spectator => game_state.clone(),
+            }
+        };
+        if connection.send(Message::Game(game_state)).is_err() {
+            return;
+        }
+        let message = match join_as {
+            JoinAs::Player => {
+                *taken = token;
+                server.announcer.lock().unwrap().take();
+                ServerMessage::GuestConnected(connection)
+            }
+            JoinAs::Spectator => ServerMessage::SpectatorJoined(connection),
+        };
+        server.messages.lock().unwrap().push_back(message);
+    }
+
+    /// Reads the `Hello` a peer starts with and tells it whether we can play together,
+    /// `ruleset` being the game it would join. Returns the peer's introduction,
+    /// or why it was refused, unless the peer left before the handshake was done.
+    pub(super) async fn answer_hello(
+        socket: &mut WebSocket,
+        ruleset: Option<&Ruleset>,
+    ) -> Option<Result<Hello, String>> {
+        let Some(Ok(ws::Message::Text(text))) = socket.recv().await else {
+            return None;
+        };
+        let hello = Hello::new();
+        let check = match serde_json::from_str::<Hello>(&text) {
+            Ok(peer) => hello.check(&peer, ruleset).map(|()| peer),
+            Err(_) => Err("the peer's version of term-nim can't be understood".into()),
+        };
+
+        let reply = match &check {
+            Ok(_) => Handshake::Accepted(hello),
+            Err(reason) => Handshake::Refused(reason.clone()),
+        };
+        let json = serde_json::to_string(&reply).unwrap();
+        socket.send(ws::Message::Text(json)).await.ok()?;
+        Some(check)
+    }
+
+    /// Turns a socket that went through the handshake into a connection.
+    pub(super) fn accept(socket: WebSocket) -> Connection {
+        Connection::spawn(socket, ws::Message::Text, |message| match message {
+            ws::Message::Text(text) => Some(text),
+            _ => None,
+        })
+    }
+}
+
+/// A server hosting many games at once on a well-known address, so players only need to
+/// reach it rather than each other. It keeps its own copy of every game, checks each move
+/// against it and relays it to the opponent and the spectators.
+pub mod lobby {
+    use std::{
+        collections::HashMap,
+        fmt::{self, Display},
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        extract::{ws::WebSocket, Path, Query, State, WebSocketUpgrade},
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::get,
+        Json, Router,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::{
+        server::{accept, answer_hello},
+        Connection, Message, Outbox, Rejoin, SeatToken,
+    };
+    use crate::{
+        chat::ChatLine,
+        game::{
+            summarize_piles, Clock, GameState, Move, PlayConvention, PlayerNames, PlayerType,
+            Ruleset,
+        },
+    };
+
+    pub const LOBBY_PORT: u16 = 4090;
+
+    /// How often the lobby looks for players who ran out of time.
+    const CLOCK_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// A game open in the lobby, as listed to clients.
+    #[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+    pub struct Listing {
+        pub id: u32,
+        pub host_name: String,
+        pub piles: Vec<u32>,
+        pub ruleset: Ruleset,
+        pub convention: PlayConvention,
+        /// How many of the two seats are taken.
+        pub players: usize,
+        pub spectators: usize,
+    }
+
+    impl Display for Listing {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let piles = summarize_piles(&self.piles);
+            write!(
+                f,
+                "#{} {} - {} ({}) - {piles} - {}/2 players",
+                self.id, self.host_name, self.ruleset, self.convention, self.players
+            )
+        }
+    }
+
+    /// A game being played in the lobby.
+    struct Table {
+        /// The game as seen from the host's seat.
+        game: GameState,
+        host: Option<Outbox>,
+        guest: Option<Outbox>,
+        /// Tokens of the seats given out, a seat is kept for whoever holds its token.
+        host_token: Option<SeatToken>,
+        guest_token: Option<SeatToken>,
+        spectators: Vec<Outbox>,
+        /// Who asked for a rematch, while waiting for the other player to agree.
+        rematch: Option<PlayerType>,
+    }
+
+    impl Table {
+        fn seat(&mut self, player: &PlayerType) -> &mut Option<Outbox> {
+            match player {
+                PlayerType::Host => &mut self.host,
+                PlayerType::Guest => &mut self.guest,
+            }
+        }
+
+        fn token(&mut self, player: &PlayerType) -> &mut Option<SeatToken> {
+            match player {
+                PlayerType::Host => &mut self.host_token,
+                PlayerType::Guest => &mut self.guest_token,
+            }
+        }
+
+        /// The game as whoever sits at `player` sees it.
+        fn view(&self, player: &PlayerType) -> GameState {
+            match player {
+                PlayerType::Host => self.game.clone(),
+                PlayerType::Guest => self.game.for_opponent(),
+            }
+        }
+
+        fn listing(&self, id: u32) -> Listing {
+            Listing {
+                id,
+                host_name: self.game.names.host.clone(),
+                piles: self.game.piles.clone(),
+                ruleset: self.game.ruleset.clone(),
+                convention: self.game.convention,
+                players: self.host_token.iter().chain(&self.guest_token).count(),
+                spectators: self.spectators.len(),
+            }
+        }
+
+        /// Plays a move sent from `player`'s seat, relaying it if legal and correcting
+        /// the sender otherwise.
+        fn play(&mut self, mv: &Move, player: &PlayerType) {
+            let mut opponent = player.clone();
+            opponent.flip();
+            let result = match self.seat(&opponent) {
+                Some(_) => self.game.play_move(mv, player).map_err(|e| e.to_string()),
+                None => Err("the opponent isn't there".into()),
+            };
+
+            match result {
+                Ok(()) => {
+                    if let Some(opponent) = self.seat(&opponent) {
+                        let _ = opponent.send(Message::Move(*mv));
+                    }
+                    for seat in self.host.iter().chain(&self.guest) {
+                        let _ = seat.send(Message::Clock(self.game.clock.clone()));
+                    }
+                    self.update_spectators();
+                }
+                Err(reason) => {
+                    let game = self.view(player);
+                    if let Some(sender) = self.seat(player) {
+                        let _ = sender.send(Message::Rejected(reason));
+                        let _ = sender.send(Message::Game(game));
+                    }
+                }
+            }
+        }
+
+        /// Starts a rematch once both players asked for one, otherwise passes the request on.
+        fn ask_rematch(&mut self, player: &PlayerType) {
+            if !self.game.is_game_over() {
+                return;
+            }
+            let mut opponent = player.clone();
+            opponent.flip();
+            if self.rematch.as_ref() == Some(&opponent) {
+                self.rematch = None;
+                self.game = self.game.rematch();
+                self.game.start_clock();
+                self.update_seat(player);
+                self.update_seat(&opponent);
+                self.update_spectators();
+                return;
+            }
+            self.rematch = Some(player.clone());
+            if let Some(opponent) = self.seat(&opponent) {
+                let _ = opponent.send(Message::Rematch);
+            }
+        }
+
+        /// Passes a line said by `player` on to everyone else at the table.
+        fn say(&mut self, player: &PlayerType, text: &str) {
+            let line = ChatLine::new(self.game.names.get(player), text);
+            let opponent = match player {
+                PlayerType::Host => &self.guest,
+                PlayerType::Guest => &self.host,
+            };
+            for listener in opponent.iter().chain(&self.spectators) {
+                let _ = listener.send(Message::Chat(line.clone()));
+            }
+        }
+
+        /// Tells `player` whether their opponent is there, and the game as it now is.
+        fn update_seat(&mut self, player: &PlayerType) {
+            let mut opponent = player.clone();
+            opponent.flip();
+            let present = self.seat(&opponent).is_some();
+            let game = self.view(player);
+            if let Some(seat) = self.seat(player) {
+                let _ = seat.send(Message::Game(game));
+                let _ = seat.send(Message::OpponentPresent(present));
+            }
+        }
+
+        /// Ends the game if the player on move ran out of time, telling everyone.
+        fn check_time(&mut self) {
+            if self.game.check_time() {
+                self.update_seat(&PlayerType::Host);
+                self.update_seat(&PlayerType::Guest);
+                self.update_spectators();
+            }
+        }
+
+        /// Sends the game to every spectator, and their count to the players.
+        fn update_spectators(&mut self) {
+            self.game.spectators = self.spectators.len();
+            for spectator in &self.spectators {
+                let _ = spectator.send(Message::Game(self.game.clone()));
+            }
+            for seat in self.host.iter().chain(&self.guest) {
+                let _ = seat.send(Message::Spectators(self.spectators.len()));
+            }
+        }
+    }
+
+    /// Every game open on the server, by ID.
+    #[derive(Default)]
+    pub struct Lobby {
+        tables: Mutex<HashMap<u32, Table>>,
+        next_id: Mutex<u32>,
+    }
+
+    impl Lobby {
+        /// Serves the lobby on `addr` until the server fails.
+        pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<(), String> {
+            let lobby = self.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(CLOCK_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    for table in lobby.tables.lock().unwrap().values_mut() {
+                        table.check_time();
+                    }
+                }
+            });
+
+            let app = Router::new()
+                .route("/", get(root))
+                .route("/games", get(games))
+                .route("/create", get(create))
+                .route("/games/:id/connect", get(connect))
+                .route("/games/:id/watch", get(watch))
+                .with_state(self);
+            axum::Server::try_bind(&addr)
+                .map_err(|e| e.to_string())?
+                .serve(app.into_make_service())
+                .await
+                .map_err(|e| e.to_string())
+        }
+
+        fn open(&self, game: GameState, host: Outbox, token: SeatToken) -> u32 {
+            let id = {
+                let mut next_id = self.next_id.lock().unwrap();
+                *next_id += 1;
+                *next_id
+            };
+            let table = Table {
+                game,
+                host: Some(host),
+                guest: None,
+                host_token: Some(token),
+                guest_token: None,
+                spectators: Vec::new(),
+                rematch: None,
+            };
+            self.tables.lock().unwrap().insert(id, table);
+            tracing::info!("game {id} opened");
+            id
+        }
+
+        /// Relays what is sent from `player`'s seat, freeing it once they leave,
+        /// unless they already took it back through another connection.
+        async fn play(&self, id: u32, player: PlayerType, mut connection: Connection) {
+            let outbox = connection.outbox();
+            let mut opponent = player.clone();
+            opponent.flip();
+            while let Ok(message) = connection.recv().await {
+                let mut tables = self.tables.lock().unwrap();
+                let Some(table) = tables.get_mut(&id) else {
+                    return;
+                };
+                match message {
+                    Message::Move(mv) => table.play(&mv, &player),
+                    Message::Rematch => table.ask_rematch(&player),
+                    Message::Chat(line) => table.say(&player, &line.text),
+                    Message::Left => {
+                        if let Some(opponent) = table.seat(&opponent) {
+                            let _ = opponent.send(Message::Left);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut tables = self.tables.lock().unwrap();
+            let Some(table) = tables.get_mut(&id) else {
+                return;
+            };
+            let seat = table.seat(&player);
+            if !seat
+                .as_ref()
+                .is_some_and(|seated| seated.same_connection(&outbox))
+            {
+                return;
+            }
+            *seat = None;
+            table.rematch = None;
+            // time doesn't run while a seat is empty
+            table.game.stop_clock();
+            if table.host.is_none() && table.guest.is_none() {
+                for spectator in &table.spectators {
+                    let _ = spectator.send(Message::Left);
+                }
+                tables.remove(&id);
+                tracing::info!("game {id} closed");
+                return;
+            }
+            table.update_seat(&opponent);
+        }
+    }
+
+    async fn root() -> &'static str {
+        "term-nim lobby"
+    }
+
+    async fn games(State(lobby): State<Arc<Lobby>>) -> Json<Vec<Listing>> {
+        let tables = lobby.tables.lock().unwrap();
+        let mut games: Vec<Listing> = tables
+            .iter()
+            .map(|(id, table)| table.listing(*id))
+            .collect();
+        games.sort_by_key(|game| game.id);
+        Json(games)
+    }
+
+    /// Opens the game the client sends after the handshake, seating them as its host.
+    async fn create(State(lobby): State<Arc<Lobby>>, upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(move |mut socket| async move {
+            let Some(Ok(host)) = answer_hello(&mut socket, None).await else {
+                return;
+            };
+            let mut connection = accept(socket);
+            let Ok(Message::Game(mut game)) = connection.recv().await else {
+                return;
+            };
+            if let Err(e) = game.validate() {
+                let _ = connection.send(Message::Rejected(e));
+                return;
+            }
+            game.player_type = PlayerType::Host;
+            game.spectators = 0;
+            game.clock = Clock::new(&game.time_control);
+            game.timed_out = None;
+            game.names = PlayerNames::default();
+            game.names.set(&PlayerType::Host, &host.player_name);
+
+            let token = SeatToken::new();
+            let id = lobby.open(game, connection.outbox(), token);
+            let _ = connection.send(Message::Seat(token));
+            let _ = connection.send(Message::GameCreated(id));
+            lobby.play(id, PlayerType::Host, connection).await;
+        })
+    }
+
+    /// Seats a player at the game, back in their seat if they present its token,
+    /// otherwise in whichever seat was never taken.
+    async fn connect(
+        State(lobby): State<Arc<Lobby>>,
+        Path(id): Path<u32>,
+        Query(rejoin): Query<Rejoin>,
+        upgrade: WebSocketUpgrade,
+    ) -> Response {
+        let Some(ruleset) = ruleset(&lobby, id) else {
+            return (StatusCode::NOT_FOUND, "No such game").into_response();
+        };
+        upgrade.on_upgrade(move |mut socket| async move {
+            let Some(Ok(peer)) = answer_hello(&mut socket, Some(&ruleset)).await else {
+                return;
+            };
+            let connection = accept(socket);
+            let player = {
+                let mut tables = lobby.tables.lock().unwrap();
+                let Some(table) = tables.get_mut(&id) else {
+                    let _ = connection.send(Message::Rejected("the game is over".into()));
+                    return;
+                };
+                let player = match (rejoin.seat, table.host_token, table.guest_token) {
+                    (Some(seat), Some(host), _) if seat == host => PlayerType::Host,
+                    (Some(seat), _, Some(guest)) if seat == guest => PlayerType::Guest,
+                    (_, None, _) => PlayerType::Host,
+                    (_, _, None) => PlayerType::Guest,
+                    _ => {
+                        let _ = connection.send(Message::Rejected("the game is full".into()));
+                        return;
+                    }
+                };
+                let token = *table.token(&player).get_or_insert_with(SeatToken::new);
+                let _ = connection.send(Message::Seat(token));
+                table.game.names.set(&player, &peer.player_name);
+                *table.seat(&player) = Some(connection.outbox());
+                if table.host.is_some() && table.guest.is_some() {
+                    table.game.start_clock();
+                }
+                let mut opponent = player.clone();
+                opponent.flip();
+                table.update_seat(&player);
+                table.update_seat(&opponent);
+                player
+            };
+            lobby.play(id, player, connection).await;
+        })
+    }
+
+    async fn watch(
+        State(lobby): State<Arc<Lobby>>,
+        Path(id): Path<u32>,
+        upgrade: WebSocketUpgrade,
+    ) -> Response {
+        let Some(ruleset) = ruleset(&lobby, id) else {
+            return (StatusCode::NOT_FOUND, "No such game").into_response();
+        };
+        upgrade.on_upgrade(move |mut socket| async move {
+            let Some(Ok(_)) = answer_hello(&mut socket, Some(&ruleset)).await else {
+                return;
+            };
+            spectate(lobby, id, socket).await;
+        })
+    }
+
+    /// Keeps a spectator up to date until they leave.
+    async fn spectate(lobby: Arc<Lobby>, id: u32, socket: WebSocket) {
+        let mut connection = accept(socket);
+        let outbox = connection.outbox();
+        {
+            let mut tables = lobby.tables.lock().unwrap();
+            let Some(table) = tables.get_mut(&id) else {
+                let _ = connection.send(Message::Rejected("the game is over".into()));
+                return;
+            };
+            table.spectators.push(outbox.clone());
+            table.update_spectators();
+        }
+
+        while connection.recv().await.is_ok() {}
+
+        if let Some(table) = lobby.tables.lock().unwrap().get_mut(&id) {
+            table
+                .spectators
+                .retain(|spectator| !spectator.same_connection(&outbox));
+            table.update_spectators();
+        }
+    }
+
+    fn ruleset(lobby: &Lobby, id: u32) -> Option<Ruleset> {
+        let tables = lobby.tables.lock().unwrap();
+        tables.get(&id).map(|table| table.game.ruleset.clone())
     }
 }
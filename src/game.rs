@@ -1,40 +1,56 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
 use crossterm::event::KeyCode;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use tui::{
     backend::Backend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style},
     widgets::{Block, BorderType, Borders, Paragraph},
     Frame,
 };
 
-use crate::ui::{popup::Popup, utils::get_center_of_rect_for_text};
+use crate::{
+    ai::Difficulty,
+    chat::{Chat, PANEL_WIDTH},
+    ui::{
+        popup::Popup,
+        utils::{get_center_of_rect_for_text, get_grid_for_cells, get_last_rows_for_rect},
+    },
+};
 
 pub enum PileAmount {
     Two,
     Five,
     Ten,
+    /// Piles entered by the player, see `PileSetup`.
+    Custom,
 }
 
 impl PileAmount {
-    pub fn amount(&self) -> usize {
+    pub fn amount(&self) -> Option<usize> {
         match self {
-            Self::Two => 2,
-            Self::Five => 5,
-            Self::Ten => 10,
+            Self::Two => Some(2),
+            Self::Five => Some(5),
+            Self::Ten => Some(10),
+            Self::Custom => None,
         }
     }
 }
 
-impl ToString for PileAmount {
-    fn to_string(&self) -> String {
-        match self {
+impl Display for PileAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::Two => "Two",
             Self::Five => "Five",
             Self::Ten => "Ten",
-        }
-        .to_string()
+            Self::Custom => "Custom",
+        })
     }
 }
 
@@ -45,7 +61,7 @@ pub enum PileSize {
 }
 
 impl PileSize {
-    pub fn quantity_limit(&self) -> i8 {
+    pub fn quantity_limit(&self) -> u32 {
         match self {
             Self::Small => 5,
             Self::Medium => 10,
@@ -54,34 +70,757 @@ impl PileSize {
     }
 }
 
-impl ToString for PileSize {
-    fn to_string(&self) -> String {
-        match self {
+impl Display for PileSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::Small => "Small",
             Self::Medium => "Medium",
             Self::Large => "Large",
+        })
+    }
+}
+
+/// Most piles a game can be set up with.
+pub const MAX_PILES: usize = 100;
+/// Most sticks a pile can have.
+pub const MAX_PILE_SIZE: u32 = 1000;
+
+/// How the starting piles of a game are chosen.
+#[derive(Clone, PartialEq, Eq)]
+pub enum PileSetup {
+    /// `amount` piles of a random size up to `max_size`.
+    Random { amount: usize, max_size: u32 },
+    /// Exactly these piles.
+    Exact(Vec<u32>),
+}
+
+impl PileSetup {
+    pub fn pile_amount(&self) -> usize {
+        match self {
+            Self::Random { amount, .. } => *amount,
+            Self::Exact(piles) => piles.len(),
         }
-        .to_string()
     }
 }
 
+impl FromStr for PileSetup {
+    type Err = String;
+
+    /// Parses either a count and a max size such as "7x20", or pile sizes such as "3 4 5".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let setup = match s.split_once(['x', 'X']) {
+            Some((amount, max_size)) => {
+                let amount = amount.trim();
+                let max_size = parse_positive_list(max_size)?;
+                let (Ok(amount), [max_size]) = (amount.parse(), max_size.as_slice()) else {
+                    return Err("Expected a count and a max size, such as 7x20".into());
+                };
+                Self::Random {
+                    amount,
+                    max_size: *max_size,
+                }
+            }
+            None => Self::Exact(parse_positive_list(s)?),
+        };
+
+        let largest = match &setup {
+            Self::Random { max_size, .. } => *max_size,
+            Self::Exact(piles) => piles.iter().copied().max().unwrap_or(0),
+        };
+        match setup.pile_amount() {
+            0 => Err("Enter at least one pile".into()),
+            amount if amount > MAX_PILES => Err(format!("At most {MAX_PILES} piles")),
+            _ if largest > MAX_PILE_SIZE => Err(format!("At most {MAX_PILE_SIZE} sticks per pile")),
+            _ => Ok(setup),
+        }
+    }
+}
+
+/// Parses positive numbers separated by spaces or commas.
+fn parse_positive_list(s: &str) -> Result<Vec<u32>, String> {
+    s.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|number| !number.is_empty())
+        .map(|number| match number.parse::<u32>() {
+            Ok(number) if number > 0 => Ok(number),
+            _ => Err(format!("Invalid number: {number}")),
+        })
+        .collect()
+}
+
+/// Decides who wins once the last stick has been taken.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum PlayConvention {
+    /// The player who takes the last stick wins.
+    Normal,
+    /// The player who takes the last stick loses.
+    Misere,
+}
+
+impl Display for PlayConvention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Normal => "Normal",
+            Self::Misere => "Misère",
+        })
+    }
+}
+
+/// Limits on how long players can think, whoever is on move when their time runs out loses.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeControl {
+    Unlimited,
+    /// Seconds each move must be made in.
+    PerMove(u32),
+    /// Seconds each player has for the whole game, and seconds they get back after each move.
+    Clock {
+        total: u32,
+        increment: u32,
+    },
+}
+
+impl TimeControl {
+    /// Time each player starts with, `None` when unlimited.
+    fn initial(&self) -> Option<Duration> {
+        match self {
+            Self::Unlimited => None,
+            Self::PerMove(seconds) | Self::Clock { total: seconds, .. } => {
+                Some(Duration::from_secs(*seconds as u64))
+            }
+        }
+    }
+
+    /// Most time a player can have left once `moves_played` moves were played.
+    fn most_left(&self, moves_played: u64) -> Duration {
+        let increments = match self {
+            Self::Clock { increment, .. } => u64::from(*increment).saturating_mul(moves_played),
+            _ => 0,
+        };
+        self.initial().unwrap_or_default() + Duration::from_secs(increments)
+    }
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unlimited => f.write_str("Unlimited"),
+            Self::PerMove(seconds) => write!(f, "{seconds}s per move"),
+            Self::Clock { total, increment } => write!(f, "{} min + {increment}s", total / 60),
+        }
+    }
+}
+
+/// Time each player has left, as of the start of the current turn.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Clock {
+    pub host: Duration,
+    pub guest: Duration,
+}
+
+impl Clock {
+    pub fn new(time_control: &TimeControl) -> Self {
+        let initial = time_control.initial().unwrap_or_default();
+        Self {
+            host: initial,
+            guest: initial,
+        }
+    }
+
+    pub fn get(&self, player: &PlayerType) -> Duration {
+        match player {
+            PlayerType::Host => self.host,
+            PlayerType::Guest => self.guest,
+        }
+    }
+
+    fn get_mut(&mut self, player: &PlayerType) -> &mut Duration {
+        match player {
+            PlayerType::Host => &mut self.host,
+            PlayerType::Guest => &mut self.guest,
+        }
+    }
+}
+
+/// Formats a time left as minutes and seconds, rounding up so 0:00 means the time is up.
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs() + u64::from(time.subsec_nanos() > 0);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Taking `amount` sticks from the pile at index `pile`.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Move {
+    pub pile: usize,
+    pub amount: u32,
+    /// Second pile the same amount is taken from, as allowed by Wythoff's game.
+    pub paired_pile: Option<usize>,
+    /// Size of the first of the two piles what is left of the pile is split into,
+    /// as allowed by Lasker's Nim and Kayles.
+    pub split: Option<u32>,
+}
+
+impl Move {
+    pub fn new(pile: usize, amount: u32) -> Self {
+        Self {
+            pile,
+            amount,
+            paired_pile: None,
+            split: None,
+        }
+    }
+}
+
+impl Display for Move {
+    /// Writes the move as the pile and the amount taken, "3-2" takes 2 sticks from pile 3,
+    /// "1&2-3" takes 3 from both piles 1 and 2, and "3-2/1" then splits what is left of
+    /// pile 3 into a pile of 1 and the rest. A split taking nothing is written "3/1".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pile + 1)?;
+        if let Some(paired_pile) = self.paired_pile {
+            write!(f, "&{}", paired_pile + 1)?;
+        }
+        if self.amount > 0 || self.split.is_none() {
+            write!(f, "-{}", self.amount)?;
+        }
+        if let Some(first) = self.split {
+            write!(f, "/{first}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    /// Reads a move written as `Display` writes it, such as "3-2", "1&2-3" or "3/1".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid move: {s}");
+        let (rest, split) = match s.split_once('/') {
+            Some((rest, first)) => (rest, Some(first.parse().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let (piles, amount) = match rest.split_once('-') {
+            Some((piles, amount)) => (piles, amount.parse().map_err(|_| invalid())?),
+            None if split.is_some() => (rest, 0),
+            None => return Err(invalid()),
+        };
+        // piles are numbered from 1
+        let index = |pile: &str| match pile.parse::<usize>() {
+            Ok(pile) if pile > 0 => Ok(pile - 1),
+            _ => Err(invalid()),
+        };
+        let (pile, paired_pile) = match piles.split_once('&') {
+            Some((pile, paired_pile)) => (index(pile)?, Some(index(paired_pile)?)),
+            None => (index(piles)?, None),
+        };
+        Ok(Self {
+            pile,
+            amount,
+            paired_pile,
+            split,
+        })
+    }
+}
+
+/// A move as it was played, kept so the game can be looked back on.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PlayedMove {
+    pub player: PlayerType,
+    pub mv: Move,
+    /// Piles left after the move.
+    pub piles: Vec<u32>,
+}
+
+/// Writes a position as its pile sizes, such as "3 0 5".
+pub fn format_piles(piles: &[u32]) -> String {
+    let piles: Vec<String> = piles.iter().map(|pile| pile.to_string()).collect();
+    piles.join(" ")
+}
+
+/// Writes the starting piles of a listed game, only counting them when there are too many to read.
+pub fn summarize_piles(piles: &[u32]) -> String {
+    match piles.len() {
+        0..=5 => format_piles(piles),
+        count => format!("{count} piles"),
+    }
+}
+
+/// Rules of a game from the Nim family, played on a list of piles.
+pub trait Rules {
+    /// Returns every move that can be played from the position.
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move>;
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        self.legal_moves(piles).contains(mv)
+    }
+
+    /// Applies a move, which must be legal, to the position.
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move);
+
+    fn is_terminal(&self, piles: &[u32]) -> bool {
+        self.legal_moves(piles).is_empty()
+    }
+
+    /// Whether moves can take from a second, paired, pile.
+    fn allows_paired_moves(&self) -> bool {
+        false
+    }
+
+    /// Whether moves can split a pile in two.
+    fn allows_splits(&self) -> bool {
+        false
+    }
+
+    /// Returns the winner of a finished game given who would be the next to move,
+    /// `None` while the game is still going.
+    fn winner(
+        &self,
+        piles: &[u32],
+        to_move: &PlayerType,
+        convention: PlayConvention,
+    ) -> Option<PlayerType> {
+        if !self.is_terminal(piles) {
+            return None;
+        }
+
+        let mut winner = to_move.clone();
+        if convention == PlayConvention::Normal {
+            // the player to move is the one who cannot move
+            winner.flip();
+        }
+        Some(winner)
+    }
+}
+
+/// Classic Nim, any amount can be taken from a single pile.
+pub struct Nim;
+
+impl Rules for Nim {
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move> {
+        piles
+            .iter()
+            .enumerate()
+            .flat_map(|(pile, &size)| (1..=size).map(move |amount| Move::new(pile, amount)))
+            .collect()
+    }
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        mv.paired_pile.is_none()
+            && mv.split.is_none()
+            && piles
+                .get(mv.pile)
+                .is_some_and(|&size| (1..=size).contains(&mv.amount))
+    }
+
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move) {
+        piles[mv.pile] -= mv.amount;
+    }
+
+    fn is_terminal(&self, piles: &[u32]) -> bool {
+        piles.iter().all(|&pile| pile == 0)
+    }
+}
+
+/// Subtraction game, the amount taken from a pile must belong to a fixed set.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Subtraction {
+    /// Allowed amounts, sorted and without duplicates.
+    pub set: Vec<u32>,
+}
+
+impl Rules for Subtraction {
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move> {
+        piles
+            .iter()
+            .enumerate()
+            .flat_map(|(pile, &size)| {
+                self.set
+                    .iter()
+                    .take_while(move |&&amount| amount <= size)
+                    .map(move |&amount| Move::new(pile, amount))
+            })
+            .collect()
+    }
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        mv.paired_pile.is_none()
+            && mv.split.is_none()
+            && self.set.contains(&mv.amount)
+            && piles.get(mv.pile).is_some_and(|&size| mv.amount <= size)
+    }
+
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move) {
+        piles[mv.pile] -= mv.amount;
+    }
+}
+
+impl FromStr for Subtraction {
+    type Err = String;
+
+    /// Parses a set of amounts separated by spaces or commas, such as "1 3 4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = parse_positive_list(s)?;
+        if set.is_empty() {
+            return Err("Enter at least one amount".into());
+        }
+
+        set.sort_unstable();
+        set.dedup();
+        Ok(Self { set })
+    }
+}
+
+impl Display for Subtraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set: Vec<String> = self.set.iter().map(|amount| amount.to_string()).collect();
+        f.write_str(&set.join(" "))
+    }
+}
+
+/// Wythoff's game, either any amount is taken from one pile or the same amount from two.
+pub struct Wythoff;
+
+impl Rules for Wythoff {
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move> {
+        let mut moves = Nim.legal_moves(piles);
+        for (pile, &size) in piles.iter().enumerate() {
+            for (paired_pile, &paired_size) in piles.iter().enumerate().skip(pile + 1) {
+                moves.extend((1..=size.min(paired_size)).map(|amount| Move {
+                    pile,
+                    amount,
+                    paired_pile: Some(paired_pile),
+                    split: None,
+                }));
+            }
+        }
+        moves
+    }
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        let Some(paired_pile) = mv.paired_pile else {
+            return Nim.is_legal(piles, mv);
+        };
+        mv.split.is_none()
+            && paired_pile != mv.pile
+            && [mv.pile, paired_pile].iter().all(|&pile| {
+                piles
+                    .get(pile)
+                    .is_some_and(|&size| (1..=size).contains(&mv.amount))
+            })
+    }
+
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move) {
+        piles[mv.pile] -= mv.amount;
+        if let Some(paired_pile) = mv.paired_pile {
+            piles[paired_pile] -= mv.amount;
+        }
+    }
+
+    fn is_terminal(&self, piles: &[u32]) -> bool {
+        Nim.is_terminal(piles)
+    }
+
+    fn allows_paired_moves(&self) -> bool {
+        true
+    }
+}
+
+/// Takes `mv.amount` sticks from a pile, then splits what is left of it in two if the move asks to.
+fn apply_split(piles: &mut Vec<u32>, mv: &Move) {
+    let rest = piles[mv.pile] - mv.amount;
+    match mv.split {
+        Some(first) => {
+            piles[mv.pile] = first;
+            piles.insert(mv.pile + 1, rest - first);
+        }
+        None => piles[mv.pile] = rest,
+    }
+}
+
+/// Whether a split leaves two non empty piles out of `rest` sticks.
+fn is_valid_split(split: Option<u32>, rest: u32) -> bool {
+    split.is_none_or(|first| 0 < first && first < rest)
+}
+
+/// Lasker's Nim, any amount can be taken from a pile, or a pile can be split in two.
+pub struct Lasker;
+
+impl Rules for Lasker {
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move> {
+        let mut moves = Nim.legal_moves(piles);
+        for (pile, &size) in piles.iter().enumerate() {
+            moves.extend((1..=size / 2).map(|first| Move {
+                split: Some(first),
+                ..Move::new(pile, 0)
+            }));
+        }
+        moves
+    }
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        let Some(&size) = piles.get(mv.pile) else {
+            return false;
+        };
+        match mv.split {
+            Some(_) => mv.paired_pile.is_none() && mv.amount == 0 && is_valid_split(mv.split, size),
+            None => Nim.is_legal(piles, mv),
+        }
+    }
+
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move) {
+        apply_split(piles, mv);
+    }
+
+    fn is_terminal(&self, piles: &[u32]) -> bool {
+        Nim.is_terminal(piles)
+    }
+
+    fn allows_splits(&self) -> bool {
+        true
+    }
+}
+
+/// Kayles, one or two pins are knocked down from a row, which may split it in two.
+pub struct Kayles;
+
+impl Rules for Kayles {
+    fn legal_moves(&self, piles: &[u32]) -> Vec<Move> {
+        let mut moves = vec![];
+        for (pile, &size) in piles.iter().enumerate() {
+            for amount in (1..=2).filter(|&amount| amount <= size) {
+                moves.push(Move::new(pile, amount));
+                let rest = size - amount;
+                moves.extend((1..=rest / 2).map(|first| Move {
+                    split: Some(first),
+                    ..Move::new(pile, amount)
+                }));
+            }
+        }
+        moves
+    }
+
+    fn is_legal(&self, piles: &[u32], mv: &Move) -> bool {
+        mv.paired_pile.is_none()
+            && (1..=2).contains(&mv.amount)
+            && piles.get(mv.pile).is_some_and(|&size| {
+                mv.amount <= size && is_valid_split(mv.split, size - mv.amount)
+            })
+    }
+
+    fn apply(&self, piles: &mut Vec<u32>, mv: &Move) {
+        apply_split(piles, mv);
+    }
+
+    fn is_terminal(&self, piles: &[u32]) -> bool {
+        Nim.is_terminal(piles)
+    }
+
+    fn allows_splits(&self) -> bool {
+        true
+    }
+}
+
+/// Which `Rules` a game is played with, this is what gets stored and sent over the network.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum Ruleset {
+    Nim,
+    Subtraction(Subtraction),
+    Wythoff,
+    Lasker,
+    Kayles,
+}
+
+impl Ruleset {
+    /// Every variant, Subtraction with an empty set.
+    pub const ALL: &'static [Ruleset] = &[
+        Self::Nim,
+        Self::Subtraction(Subtraction { set: Vec::new() }),
+        Self::Wythoff,
+        Self::Lasker,
+        Self::Kayles,
+    ];
+
+    /// Names of every variant, so peers can tell which ones they share.
+    pub const NAMES: [&'static str; Self::ALL.len()] = {
+        let mut names = [""; Self::ALL.len()];
+        let mut variant = 0;
+        while variant < names.len() {
+            names[variant] = Self::ALL[variant].name();
+            variant += 1;
+        }
+        names
+    };
+
+    /// Name the variant is displayed with.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Nim => "Nim",
+            Self::Subtraction(_) => "Subtraction",
+            Self::Wythoff => "Wythoff",
+            Self::Lasker => "Lasker's Nim",
+            Self::Kayles => "Kayles",
+        }
+    }
+
+    /// Number of piles the variant is always played on, if any.
+    pub fn fixed_pile_amount(&self) -> Option<usize> {
+        match self {
+            Self::Wythoff => Some(2),
+            _ => None,
+        }
+    }
+
+    pub fn rules(&self) -> &dyn Rules {
+        match self {
+            Self::Nim => &Nim,
+            Self::Subtraction(subtraction) => subtraction,
+            Self::Wythoff => &Wythoff,
+            Self::Lasker => &Lasker,
+            Self::Kayles => &Kayles,
+        }
+    }
+}
+
+impl Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Keys offered once a game is over, see `AfterGame`.
+pub const GAME_OVER_KEYS: &str = "Enter: rematch, N: new settings, M: main menu";
+
+/// What a player can pick once the game is over.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AfterGame {
+    /// The same settings again, the other player starting.
+    Rematch,
+    NewSettings,
+    MainMenu,
+}
+
+impl AfterGame {
+    pub fn from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::Enter => Some(Self::Rematch),
+            KeyCode::Char('n' | 'N') => Some(Self::NewSettings),
+            KeyCode::Char('m' | 'M') => Some(Self::MainMenu),
+            _ => None,
+        }
+    }
+}
+
+/// Most seconds a time control can give.
+const MAX_TIME_CONTROL: u32 = 24 * 60 * 60;
+
+/// Longest name a player can be shown with.
+pub const MAX_NAME_LENGTH: usize = 24;
+
+/// Smallest size a pile is drawn at, more piles than fit on screen are scrolled through.
+const PILE_MIN_WIDTH: u16 = 16;
+const PILE_MIN_HEIGHT: u16 = 5;
+
+/// Width the move history takes next to the piles.
+const HISTORY_WIDTH: u16 = 28;
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct GameState {
-    pub piles: Vec<i8>,
+    pub piles: Vec<u32>,
+    /// Piles the game started from, a rematch starts from them again.
+    pub initial_piles: Vec<u32>,
     pub selected_pile: usize,
-    pub amount_selected: Option<i8>,
+    /// Pile marked to take the same amount from as the selected one.
+    pub paired_pile: Option<usize>,
+    pub amount_selected: Option<u32>,
+    /// Size of the first pile when splitting the selected one.
+    pub split_selected: Option<u32>,
     pub current_player: PlayerType,
     pub player_type: PlayerType,
+    pub ruleset: Ruleset,
+    pub convention: PlayConvention,
+    /// Seed the piles were generated from, also drives the computer opponent's choices.
+    pub seed: u64,
+    pub moves_played: u64,
+    /// Every move played so far, oldest first.
+    pub history: Vec<PlayedMove>,
+    /// Moves taken back, the last one taken back at the end.
+    #[serde(skip)]
+    pub undone: Vec<PlayedMove>,
+    /// Strength of the computer opponent, `None` when playing against another person.
+    pub difficulty: Option<Difficulty>,
+    pub time_control: TimeControl,
+    pub clock: Clock,
+    /// Who ran out of time, which ends the game.
+    pub timed_out: Option<PlayerType>,
+    /// When the current turn started for this copy of the game, `None` while the clock is stopped.
+    #[serde(skip)]
+    pub turn_started: Option<Instant>,
+    pub names: PlayerNames,
+    /// How many people are watching the game over the network.
+    pub spectators: usize,
+    /// Whether this copy of the game is only watched, it can't be played from.
+    #[serde(skip)]
+    pub spectating: bool,
+    /// Whether both players share this copy of the game, each playing on their turn.
+    pub hot_seat: bool,
+    /// Chat with the other side, in networked games.
+    #[serde(skip)]
+    pub chat: Option<Chat>,
+    /// Whether the finished game was written to a replay file.
+    #[serde(skip)]
+    pub recorded: bool,
 }
 
 impl Default for GameState {
     fn default() -> Self {
         Self {
             piles: vec![],
+            initial_piles: vec![],
             selected_pile: 0,
+            paired_pile: None,
             amount_selected: None,
+            split_selected: None,
             current_player: PlayerType::Host,
             player_type: PlayerType::Host,
+            ruleset: Ruleset::Nim,
+            convention: PlayConvention::Normal,
+            seed: 0,
+            moves_played: 0,
+            history: vec![],
+            undone: vec![],
+            difficulty: None,
+            time_control: TimeControl::Unlimited,
+            clock: Clock::new(&TimeControl::Unlimited),
+            timed_out: None,
+            turn_started: None,
+            names: PlayerNames::default(),
+            spectators: 0,
+            spectating: false,
+            hot_seat: false,
+            chat: None,
+            recorded: false,
+        }
+    }
+}
+
+/// Why a move received from the opponent was refused.
+#[derive(Debug)]
+pub enum MoveError {
+    GameOver,
+    NotYourTurn,
+    Illegal(Move),
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver => f.write_str("the game is over"),
+            Self::NotYourTurn => f.write_str("it is not your turn"),
+            Self::Illegal(mv) => write!(
+                f,
+                "taking {} from pile {} is not allowed",
+                mv.amount,
+                mv.pile + 1
+            ),
         }
     }
 }
@@ -101,23 +840,262 @@ impl PlayerType {
     }
 }
 
+/// Names the players are shown with, by the side they play.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct PlayerNames {
+    pub host: String,
+    pub guest: String,
+}
+
+impl PlayerNames {
+    pub fn get(&self, player: &PlayerType) -> &str {
+        match player {
+            PlayerType::Host => &self.host,
+            PlayerType::Guest => &self.guest,
+        }
+    }
+
+    /// Sets a player's name, cut down to `MAX_NAME_LENGTH` characters.
+    pub fn set(&mut self, player: &PlayerType, name: &str) {
+        let name = name.chars().take(MAX_NAME_LENGTH).collect();
+        match player {
+            PlayerType::Host => self.host = name,
+            PlayerType::Guest => self.guest = name,
+        }
+    }
+}
+
+impl Default for PlayerNames {
+    fn default() -> Self {
+        Self {
+            host: "Host".into(),
+            guest: "Guest".into(),
+        }
+    }
+}
+
 impl GameState {
-    pub fn new(pile_amount: &PileAmount, pile_sizes: &PileSize, player_type: PlayerType) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(
+        setup: &PileSetup,
+        player_type: PlayerType,
+        ruleset: Ruleset,
+        convention: PlayConvention,
+        difficulty: Option<Difficulty>,
+        time_control: TimeControl,
+    ) -> Self {
+        let seed = rand::thread_rng().gen();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let piles: Vec<u32> = match setup {
+            PileSetup::Random { amount, max_size } => {
+                (0..*amount).map(|_| rng.gen_range(1..=*max_size)).collect()
+            }
+            PileSetup::Exact(piles) => piles.clone(),
+        };
         Self {
-            piles: (0..pile_amount.amount())
-                .map(|_| rng.gen_range(1..=pile_sizes.quantity_limit()))
-                .collect(),
+            initial_piles: piles.clone(),
+            piles,
             selected_pile: 0,
+            paired_pile: None,
             current_player: player_type.clone(),
             player_type,
             amount_selected: None,
+            split_selected: None,
+            ruleset,
+            convention,
+            seed,
+            moves_played: 0,
+            history: vec![],
+            undone: vec![],
+            difficulty,
+            time_control,
+            clock: Clock::new(&time_control),
+            timed_out: None,
+            turn_started: None,
+            names: PlayerNames::default(),
+            spectators: 0,
+            spectating: false,
+            hot_seat: false,
+            chat: None,
+            recorded: false,
         }
     }
 
+    /// Plays the selected move, the selection must be a legal move.
     pub fn pick(&mut self) {
-        self.piles[self.selected_pile] -= self.amount_selected.take().unwrap();
+        let mv = self.selected_move().unwrap();
+        self.amount_selected = None;
+        self.paired_pile = None;
+        self.split_selected = None;
+        self.ruleset.rules().apply(&mut self.piles, &mv);
+
+        let left = self.clock.get_mut(&self.current_player);
+        if let Some(started) = self.turn_started {
+            *left = left.saturating_sub(started.elapsed());
+            self.turn_started = Some(Instant::now());
+        }
+        match self.time_control {
+            TimeControl::Unlimited => {}
+            TimeControl::PerMove(seconds) => *left = Duration::from_secs(seconds as u64),
+            TimeControl::Clock { increment, .. } => {
+                *left = left.saturating_add(Duration::from_secs(increment as u64))
+            }
+        }
+
+        // playing the move last taken back plays it again, any other move forgets those taken back
+        if self.undone.last().is_some_and(|played| played.mv == mv) {
+            self.undone.pop();
+        } else {
+            self.undone.clear();
+        }
+        self.history.push(PlayedMove {
+            player: self.current_player.clone(),
+            mv,
+            piles: self.piles.clone(),
+        });
         self.current_player.flip();
+        self.moves_played += 1;
+    }
+
+    /// Takes back the last move, restoring the position, the player on move and the selection
+    /// from before it was played. Returns false when there is no move to take back.
+    pub fn undo(&mut self) -> bool {
+        let Some(played) = self.history.pop() else {
+            return false;
+        };
+        self.stop_clock();
+        let previous = self.history.last().map(|previous| &previous.piles);
+        self.piles = previous.unwrap_or(&self.initial_piles).clone();
+        self.current_player = played.player.clone();
+        self.moves_played -= 1;
+        self.timed_out = None;
+        self.recorded = false;
+        self.select(&played.mv);
+        self.undone.push(played);
+        true
+    }
+
+    /// Plays the last move taken back again, returns false when there is none.
+    pub fn redo(&mut self) -> bool {
+        let Some(played) = self.undone.last() else {
+            return false;
+        };
+        let mv = played.mv;
+        self.select(&mv);
+        self.pick();
+        true
+    }
+
+    /// Time `player` has left, counting the current turn if it is theirs.
+    pub fn time_left(&self, player: &PlayerType) -> Duration {
+        let left = self.clock.get(player);
+        match self.turn_started {
+            Some(started) if *player == self.current_player => {
+                left.saturating_sub(started.elapsed())
+            }
+            _ => left,
+        }
+    }
+
+    /// Starts counting down the time of the player on move, if it wasn't already.
+    pub fn start_clock(&mut self) {
+        if self.time_control != TimeControl::Unlimited && !self.is_game_over() {
+            self.turn_started.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Stops the clock, keeping the time the player on move has used so far.
+    pub fn stop_clock(&mut self) {
+        if let Some(started) = self.turn_started.take() {
+            let left = self.clock.get_mut(&self.current_player);
+            *left = left.saturating_sub(started.elapsed());
+        }
+    }
+
+    /// Ends the game if the player on move ran out of time, returns whether it did.
+    pub fn check_time(&mut self) -> bool {
+        if self.turn_started.is_none()
+            || self.is_game_over()
+            || !self.time_left(&self.current_player).is_zero()
+        {
+            return false;
+        }
+        self.stop_clock();
+        self.timed_out = Some(self.current_player.clone());
+        true
+    }
+
+    /// Plays a move on behalf of `player`, after checking it is their turn and the move is legal.
+    pub fn play_move(&mut self, mv: &Move, player: &PlayerType) -> Result<(), MoveError> {
+        if self.is_game_over() {
+            return Err(MoveError::GameOver);
+        }
+        if self.current_player != *player {
+            return Err(MoveError::NotYourTurn);
+        }
+        if !self.ruleset.rules().is_legal(&self.piles, mv) {
+            return Err(MoveError::Illegal(*mv));
+        }
+
+        self.select(mv);
+        self.pick();
+        Ok(())
+    }
+
+    /// Moves the selection to the given move, so it can be played with `pick`.
+    pub fn select(&mut self, mv: &Move) {
+        self.selected_pile = mv.pile;
+        self.paired_pile = mv.paired_pile;
+        self.amount_selected = Some(mv.amount);
+        self.split_selected = mv.split;
+    }
+
+    pub fn selected_move(&self) -> Option<Move> {
+        Some(Move {
+            split: self.split_selected,
+            ..self.selected_move_taking(self.amount_selected?)
+        })
+    }
+
+    /// The selected move with the given amount and without splitting the pile.
+    fn selected_move_taking(&self, amount: u32) -> Move {
+        Move {
+            pile: self.selected_pile,
+            amount,
+            paired_pile: self.marked_paired_pile(),
+            split: None,
+        }
+    }
+
+    /// The paired pile, unless it is also the selected one.
+    fn marked_paired_pile(&self) -> Option<usize> {
+        self.paired_pile.filter(|&pile| pile != self.selected_pile)
+    }
+
+    /// Amounts that can legally be taken with the current selection, in increasing order.
+    fn legal_amounts(&self) -> Vec<u32> {
+        let rules = self.ruleset.rules();
+        (1..=self.piles[self.selected_pile])
+            .filter(|&amount| rules.is_legal(&self.piles, &self.selected_move_taking(amount)))
+            .collect()
+    }
+
+    /// Sizes of the first pile the selected pile can legally be split into
+    /// after taking the selected amount, in increasing order.
+    fn legal_splits(&self) -> Vec<u32> {
+        let rules = self.ruleset.rules();
+        let amount = self.amount_selected.unwrap_or(0);
+        let mv = self.selected_move_taking(amount);
+        (1..self.piles[self.selected_pile].saturating_sub(amount))
+            .filter(|&first| {
+                rules.is_legal(
+                    &self.piles,
+                    &Move {
+                        split: Some(first),
+                        ..mv
+                    },
+                )
+            })
+            .collect()
     }
 
     pub fn next(&mut self) {
@@ -129,113 +1107,424 @@ impl GameState {
     }
 
     pub fn is_game_over(&self) -> bool {
-        self.piles.iter().all(|&pile| pile == 0)
+        self.timed_out.is_some() || self.ruleset.rules().is_terminal(&self.piles)
     }
 
-    /// Handles keyboard input from the user, returns true if the game state was changed.
-    pub fn handle_key(&mut self, key: KeyCode) -> bool {
-        if self.current_player != self.player_type {
-            return false;
+    /// Who made the first move of the game.
+    pub fn first_player(&self) -> PlayerType {
+        let mut first = self.current_player.clone();
+        if self.moves_played % 2 == 1 {
+            first.flip();
+        }
+        first
+    }
+
+    /// A new game with the same settings and starting piles, started by the other player.
+    pub fn rematch(&self) -> GameState {
+        let mut first = self.first_player();
+        first.flip();
+        GameState {
+            piles: self.initial_piles.clone(),
+            selected_pile: 0,
+            paired_pile: None,
+            amount_selected: None,
+            split_selected: None,
+            current_player: first,
+            seed: rand::thread_rng().gen(),
+            moves_played: 0,
+            history: vec![],
+            undone: vec![],
+            clock: Clock::new(&self.time_control),
+            timed_out: None,
+            turn_started: None,
+            recorded: false,
+            ..self.clone()
+        }
+    }
+
+    /// The same game as seen from the other side of the network.
+    pub fn for_opponent(&self) -> GameState {
+        let mut game_state = self.clone();
+        game_state.player_type.flip();
+        game_state
+    }
+
+    /// Checks a game state received from the network, so a bad peer can't make it misbehave.
+    pub fn validate(&self) -> Result<(), String> {
+        self.check()?;
+        if self.hot_seat {
+            return Err("Local games can't be played over the network".into());
+        }
+        Ok(())
+    }
+
+    /// Most piles the game can reach from its starting piles: each split makes one more
+    /// non empty pile, and there can't be more of those than sticks.
+    fn max_piles(&self) -> usize {
+        if !self.ruleset.rules().allows_splits() {
+            return self.initial_piles.len();
+        }
+        let sticks: usize = self.initial_piles.iter().map(|&size| size as usize).sum();
+        let non_empty = self.initial_piles.iter().filter(|&&size| size > 0).count();
+        self.initial_piles.len() + sticks - non_empty
+    }
+
+    /// Checks a game state read from outside, such as a save file, is one that can be played.
+    pub fn check(&self) -> Result<(), String> {
+        if self.initial_piles.is_empty() || self.initial_piles.len() > MAX_PILES {
+            return Err(format!(
+                "Invalid number of starting piles: {}",
+                self.initial_piles.len()
+            ));
+        }
+        if let Some(amount) = self.ruleset.fixed_pile_amount() {
+            if self.initial_piles.len() != amount {
+                return Err(format!("{} is played on {amount} piles", self.ruleset));
+            }
+        }
+        if let Some(size) = self
+            .initial_piles
+            .iter()
+            .find(|&&size| size > MAX_PILE_SIZE)
+        {
+            return Err(format!("Pile of {size} sticks is too large"));
+        }
+        let max_piles = self.max_piles();
+        if self.piles.len() < self.initial_piles.len() || self.piles.len() > max_piles {
+            return Err(format!("Invalid number of piles: {}", self.piles.len()));
+        }
+        if self.history.len() as u64 != self.moves_played {
+            return Err("Move history does not match the moves played".into());
+        }
+        if self
+            .history
+            .iter()
+            .any(|played| played.piles.len() > max_piles)
+        {
+            return Err("Invalid number of piles in the move history".into());
+        }
+        let history = self.history.iter().flat_map(|played| &played.piles);
+        if let Some(size) = self
+            .piles
+            .iter()
+            .chain(history)
+            .find(|&&size| size > MAX_PILE_SIZE)
+        {
+            return Err(format!("Pile of {size} sticks is too large"));
+        }
+        if [&self.names.host, &self.names.guest]
+            .iter()
+            .any(|name| name.chars().count() > MAX_NAME_LENGTH)
+        {
+            return Err("Player name is too long".into());
+        }
+        match self.time_control {
+            TimeControl::PerMove(seconds) | TimeControl::Clock { total: seconds, .. }
+                if seconds == 0 || seconds > MAX_TIME_CONTROL =>
+            {
+                return Err(format!("Invalid time control: {}", self.time_control));
+            }
+            TimeControl::Clock { increment, .. } if increment > MAX_TIME_CONTROL => {
+                return Err(format!("Invalid time control: {}", self.time_control));
+            }
+            _ => {}
+        }
+        let most_left = self.time_control.most_left(self.moves_played);
+        if self.clock.host > most_left || self.clock.guest > most_left {
+            return Err("More time left than the time control allows".into());
+        }
+        if let Some(loser) = &self.timed_out {
+            // only the player on move can run out of time, and only before the game is won
+            if self.time_control == TimeControl::Unlimited
+                || *loser != self.current_player
+                || !self.clock.get(loser).is_zero()
+                || self.ruleset.rules().is_terminal(&self.piles)
+            {
+                return Err("Invalid time out".into());
+            }
+        }
+        if let Ruleset::Subtraction(subtraction) = &self.ruleset {
+            // moves are listed assuming the amounts are sorted without duplicates
+            let set = &subtraction.set;
+            if set.first().is_none_or(|&smallest| smallest == 0)
+                || set.last().is_some_and(|&largest| largest > MAX_PILE_SIZE)
+                || set.windows(2).any(|pair| pair[0] >= pair[1])
+            {
+                return Err("Invalid subtraction set".into());
+            }
+        }
+
+        let selected = [Some(self.selected_pile), self.paired_pile];
+        if selected
+            .iter()
+            .flatten()
+            .any(|&pile| pile >= self.piles.len())
+        {
+            return Err("Selected pile does not exist".into());
+        }
+        let size = self.piles[self.selected_pile];
+        let amount = self.amount_selected.unwrap_or(0);
+        if amount > size {
+            return Err(format!("Can't take {amount} sticks from a pile of {size}"));
+        }
+        if !is_valid_split(self.split_selected, size - amount) {
+            return Err("Selected split is not possible".into());
+        }
+
+        // the moves played must lead from the starting piles to the current ones
+        let rules = self.ruleset.rules();
+        let mut piles = self.initial_piles.clone();
+        for played in &self.history {
+            if !rules.is_legal(&piles, &played.mv) {
+                return Err(format!("Move {} in the history is not allowed", played.mv));
+            }
+            rules.apply(&mut piles, &played.mv);
+            if piles != played.piles {
+                return Err(format!(
+                    "Move {} in the history leaves other piles",
+                    played.mv
+                ));
+            }
+        }
+        if piles != self.piles {
+            return Err("Move history does not lead to the current piles".into());
+        }
+        Ok(())
+    }
+
+    /// Returns the winner of a finished game, `None` while the game is still going.
+    pub fn winner(&self) -> Option<PlayerType> {
+        if let Some(loser) = &self.timed_out {
+            let mut winner = loser.clone();
+            winner.flip();
+            return Some(winner);
+        }
+        self.ruleset
+            .rules()
+            .winner(&self.piles, &self.current_player, self.convention)
+    }
+
+    /// Handles keyboard input from the user, returns the move played, if any.
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<Move> {
+        if self.spectating || (!self.hot_seat && self.current_player != self.player_type) {
+            return None;
         }
         match key {
             KeyCode::Left => {
                 self.amount_selected = None;
+                self.split_selected = None;
                 self.previous();
             }
             KeyCode::Right => {
                 self.amount_selected = None;
+                self.split_selected = None;
                 self.next();
             }
             KeyCode::Up => {
-                if let Some(amount) = self.amount_selected {
-                    self.amount_selected = Some((amount - 1).max(0));
-                } else {
-                    self.amount_selected = Some(0);
-                }
+                self.split_selected = None;
+                let amount = self.amount_selected.unwrap_or(0);
+                let smaller = self.legal_amounts().into_iter().rev().find(|&a| a < amount);
+                self.amount_selected = Some(smaller.unwrap_or(0));
             }
             KeyCode::Down => {
-                if let Some(amount) = self.amount_selected {
-                    self.amount_selected = Some((amount + 1).min(self.piles[self.selected_pile]));
+                self.split_selected = None;
+                let amount = self.amount_selected.unwrap_or(0);
+                if let Some(larger) = self.legal_amounts().into_iter().find(|&a| a > amount) {
+                    self.amount_selected = Some(larger);
+                }
+            }
+            KeyCode::Char(' ') if self.ruleset.rules().allows_paired_moves() => {
+                self.amount_selected = None;
+                self.paired_pile = if self.paired_pile == Some(self.selected_pile) {
+                    None
                 } else {
-                    self.amount_selected = Some(1);
+                    Some(self.selected_pile)
+                };
+            }
+            KeyCode::Tab if self.ruleset.rules().allows_splits() => {
+                let current = self.split_selected.unwrap_or(0);
+                self.split_selected = self.legal_splits().into_iter().find(|&s| s > current);
+                if self.split_selected.is_some() {
+                    self.amount_selected.get_or_insert(0);
                 }
             }
             KeyCode::Enter => {
-                if let Some(amount) = self.amount_selected {
-                    if amount > 0 {
-                        self.pick();
-                    }
+                let mv = self.selected_move()?;
+                if self.ruleset.rules().is_legal(&self.piles, &mv) {
+                    self.pick();
+                    return Some(mv);
                 }
             }
-            _ => return false,
+            _ => {}
         }
-        true
+        None
+    }
+
+    /// How the game ended for whoever holds this copy, `None` while it is still going.
+    pub fn outcome(&self) -> Option<String> {
+        let winner = self.winner()?;
+        let outcome = if self.spectating || self.hot_seat {
+            format!("{} Won!", self.names.get(&winner))
+        } else if self.player_type == winner {
+            "You Won! :)".into()
+        } else {
+            "You Lose! :(".into()
+        };
+        Some(match self.timed_out {
+            Some(_) => format!("{outcome} (on time)"),
+            None => outcome,
+        })
     }
 
     pub fn render<B: Backend>(&self, frame: &mut Frame<B>) {
-        if self.is_game_over() {
-            let popup = if self.player_type != self.current_player {
-                Popup::new("Game Over".into(), "You Won! :)".into())
-            } else {
-                Popup::new("Game Over".into(), "You Lose! :(".into())
-            };
+        let prompt = if self.spectating {
+            "Enter: leave"
+        } else {
+            GAME_OVER_KEYS
+        };
+        self.render_ended(frame, prompt);
+    }
+
+    /// Renders the game, with `prompt` next to the outcome once it is over.
+    pub fn render_ended<B: Backend>(&self, frame: &mut Frame<B>, prompt: &str) {
+        let area = frame.size();
+        self.render_annotated(frame, area, prompt, &[]);
+    }
 
-            popup.render(frame);
-            return;
+    /// Renders the game in `area`, with `notes` after the moves in the history,
+    /// and `prompt` next to the outcome once it is over.
+    pub fn render_annotated<B: Backend>(
+        &self,
+        frame: &mut Frame<B>,
+        area: Rect,
+        prompt: &str,
+        notes: &[String],
+    ) {
+        self.render_board(frame, area, notes);
+        if let Some(outcome) = self.outcome() {
+            Popup::new("Game Over".into(), format!("{outcome} - {prompt}")).render(frame);
         }
+    }
 
-        let screen = frame.size();
+    /// Lists the moves played, the latest at the bottom.
+    fn render_history<B: Backend>(&self, frame: &mut Frame<B>, area: Rect, notes: &[String]) {
+        let block = Block::default().borders(Borders::ALL).title("Moves");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let lines = self.history.iter().enumerate().map(|(i, played)| {
+            format!(
+                "{}. {} {}{} => {}",
+                i + 1,
+                self.names.get(&played.player),
+                played.mv,
+                notes.get(i).map_or("", String::as_str),
+                format_piles(&played.piles)
+            )
+        });
+        let rows = get_last_rows_for_rect(&inner, lines);
+        frame.render_widget(Paragraph::new(rows), inner);
+    }
 
-        let chunks = match self.piles.len() {
-            2 => Layout::default()
+    fn render_board<B: Backend>(&self, frame: &mut Frame<B>, area: Rect, notes: &[String]) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(area);
+        let (status, mut board) = (chunks[0], chunks[1]);
+
+        if let Some(chat) = self.chat.as_ref().filter(|chat| chat.is_open()) {
+            let chunks = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-                .split(screen),
-            5 => Layout::default()
+                .constraints([Constraint::Min(0), Constraint::Length(PANEL_WIDTH)])
+                .split(board);
+            board = chunks[0];
+            chat.render(frame, chunks[1]);
+        }
+
+        if board.width >= PILE_MIN_WIDTH + HISTORY_WIDTH {
+            let chunks = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                ])
-                .split(screen),
-            10 => {
-                let rows = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-                    .split(screen);
-                rows.into_iter()
-                    .flat_map(|r| {
-                        Layout::default()
-                            .direction(Direction::Horizontal)
-                            .constraints([
-                                Constraint::Percentage(20),
-                                Constraint::Percentage(20),
-                                Constraint::Percentage(20),
-                                Constraint::Percentage(20),
-                                Constraint::Percentage(20),
-                            ])
-                            .split(r)
-                    })
-                    .collect()
-            }
-            _ => panic!("Unsupported number of piles"),
+                .constraints([Constraint::Min(0), Constraint::Length(HISTORY_WIDTH)])
+                .split(board);
+            board = chunks[0];
+            self.render_history(frame, chunks[1], notes);
+        }
+
+        let (first, cells) = get_grid_for_cells(
+            &board,
+            self.piles.len(),
+            PILE_MIN_WIDTH,
+            PILE_MIN_HEIGHT,
+            self.selected_pile,
+        );
+
+        let rules = match &self.ruleset {
+            Ruleset::Subtraction(subtraction) => format!("{} ({subtraction})", self.ruleset),
+            ruleset => ruleset.to_string(),
         };
+        let turn = self.names.get(&self.current_player);
+        let mut status_line = format!(" {rules} · {} · {turn}'s turn", self.convention);
+        if self.spectating {
+            status_line += " · Spectating";
+        }
+        match self.time_control {
+            TimeControl::Unlimited => {}
+            TimeControl::PerMove(_) => {
+                let left = self.time_left(&self.current_player);
+                status_line += &format!(" · {} left", format_time(left));
+            }
+            TimeControl::Clock { .. } => {
+                status_line += &format!(
+                    " · {} {} · {} {}",
+                    self.names.host,
+                    format_time(self.time_left(&PlayerType::Host)),
+                    self.names.guest,
+                    format_time(self.time_left(&PlayerType::Guest))
+                );
+            }
+        }
+        if self.spectators > 0 {
+            status_line += &format!(" · {} watching", self.spectators);
+        }
+        match self.chat.as_ref().map(Chat::unread) {
+            Some(0) => {}
+            Some(1) => status_line += " · 1 new message (C to read)",
+            Some(unread) => status_line += &format!(" · {unread} new messages (C to read)"),
+            None => {}
+        }
+        frame.render_widget(Paragraph::new(status_line), status);
+        if cells.len() < self.piles.len() {
+            let shown = format!(
+                "Piles {}-{} of {} ",
+                first + 1,
+                first + cells.len(),
+                self.piles.len()
+            );
+            frame.render_widget(Paragraph::new(shown).alignment(Alignment::Right), status);
+        }
+
+        let paired_pile = self.marked_paired_pile();
 
-        for (i, pile_qty) in self.piles.iter().enumerate() {
-            let pile_block = if i == self.selected_pile {
+        for (cell, (i, pile_qty)) in cells.iter().zip(self.piles.iter().enumerate().skip(first)) {
+            let pile_block = if Some(i) == paired_pile {
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Double)
+                    .border_style(Style::default().fg(Color::Yellow))
+                    .title(format!("Pile {}", i + 1))
+                    .title_alignment(Alignment::Center)
+            } else if i == self.selected_pile {
                 Block::default()
                     .borders(Borders::ALL)
                     .border_type(BorderType::Double)
-                    .border_style(
-                        Style::default().fg(if self.player_type == self.current_player {
+                    .border_style(Style::default().fg(
+                        if self.hot_seat || self.player_type == self.current_player {
                             Color::Green
                         } else {
                             Color::Red
-                        }),
-                    )
+                        },
+                    ))
                     .title(format!("Pile {}", i + 1))
                     .title_alignment(Alignment::Center)
             } else {
@@ -245,20 +1534,279 @@ impl GameState {
                     .title_alignment(Alignment::Center)
             };
 
-            frame.render_widget(pile_block, chunks[i]);
+            frame.render_widget(pile_block, *cell);
 
-            let (rect, msg) = if i == self.selected_pile {
-                let msg = format!(
-                    "{pile_qty} => {}",
-                    pile_qty - self.amount_selected.unwrap_or(0)
-                );
-                (get_center_of_rect_for_text(&chunks[i], &msg), msg)
+            let (rect, msg) = if i == self.selected_pile || Some(i) == paired_pile {
+                let rest = pile_qty.saturating_sub(self.amount_selected.unwrap_or(0));
+                let msg = match self.split_selected.filter(|_| i == self.selected_pile) {
+                    Some(first) => {
+                        format!("{pile_qty} => {first} + {}", rest.saturating_sub(first))
+                    }
+                    None => format!("{pile_qty} => {rest}"),
+                };
+                (get_center_of_rect_for_text(cell, &msg), msg)
             } else {
                 let msg = pile_qty.to_string();
-                (get_center_of_rect_for_text(&chunks[i], &msg), msg)
+                (get_center_of_rect_for_text(cell, &msg), msg)
             };
 
             frame.render_widget(Paragraph::new(msg), rect);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_notation_round_trips() {
+        let moves = [
+            (Move::new(2, 2), "3-2"),
+            (
+                Move {
+                    paired_pile: Some(1),
+                    ..Move::new(0, 3)
+                },
+                "1&2-3",
+            ),
+            (
+                Move {
+                    split: Some(1),
+                    ..Move::new(2, 2)
+                },
+                "3-2/1",
+            ),
+            (
+                Move {
+                    split: Some(1),
+                    ..Move::new(2, 0)
+                },
+                "3/1",
+            ),
+        ];
+        for (mv, notation) in moves {
+            assert_eq!(mv.to_string(), notation);
+            assert_eq!(notation.parse::<Move>(), Ok(mv));
+        }
+    }
+
+    #[test]
+    fn invalid_move_notation() {
+        for notation in ["", "3", "0-1", "3-", "-2", "1&-2", "3-2/", "a-1"] {
+            assert!(notation.parse::<Move>().is_err(), "{notation}");
+        }
+    }
+
+    fn play(game: &mut GameState, mv: Move) {
+        let player = game.current_player.clone();
+        game.play_move(&mv, &player).unwrap();
+    }
+
+    #[test]
+    fn undo_then_redo_restores_the_game() {
+        let mut game = GameState::new(
+            &PileSetup::Exact(vec![3, 4, 5]),
+            PlayerType::Host,
+            Ruleset::Nim,
+            PlayConvention::Normal,
+            None,
+            TimeControl::Unlimited,
+        );
+        game.hot_seat = true;
+        play(&mut game, Move::new(0, 2));
+        play(&mut game, Move::new(2, 5));
+        let played = serde_json::to_value(&game).unwrap();
+
+        assert!(game.undo() && game.undo());
+        assert!(!game.undo());
+        assert_eq!(game.piles, [3, 4, 5]);
+        assert!(game.redo() && game.redo());
+        assert!(!game.redo());
+        assert_eq!(serde_json::to_value(&game).unwrap(), played);
+
+        // a new move forgets the ones taken back
+        game.undo();
+        play(&mut game, Move::new(1, 1));
+        assert!(!game.redo());
+        assert_eq!(game.piles, [1, 3, 5]);
+    }
+
+    fn new_game(piles: Vec<u32>, ruleset: Ruleset) -> GameState {
+        GameState::new(
+            &PileSetup::Exact(piles),
+            PlayerType::Host,
+            ruleset,
+            PlayConvention::Normal,
+            None,
+            TimeControl::Unlimited,
+        )
+    }
+
+    /// A game two moves in, that passes the checks as it is.
+    fn played_game() -> GameState {
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        play(&mut game, Move::new(0, 2));
+        play(&mut game, Move::new(2, 5));
+        game.validate().unwrap();
+        game
+    }
+
+    fn assert_rejected(game: &GameState) {
+        assert!(game.check().is_err());
+        assert!(game.validate().is_err());
+    }
+
+    #[test]
+    fn check_rejects_a_history_that_does_not_replay() {
+        let mut game = played_game();
+        game.history[0].piles[1] = 3;
+        assert_rejected(&game);
+
+        let mut game = played_game();
+        game.history[1].mv = Move::new(2, 6);
+        assert_rejected(&game);
+
+        let mut game = played_game();
+        game.piles[1] = 2;
+        assert_rejected(&game);
+
+        let mut game = played_game();
+        game.moves_played += 1;
+        assert_rejected(&game);
+    }
+
+    #[test]
+    fn check_rejects_oversized_piles() {
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        game.initial_piles[0] = MAX_PILE_SIZE + 1;
+        game.piles[0] = MAX_PILE_SIZE + 1;
+        assert_rejected(&game);
+
+        let mut game = new_game(vec![1; MAX_PILES + 1], Ruleset::Nim);
+        game.initial_piles = game.piles.clone();
+        assert_rejected(&game);
+
+        // splits can't make more piles than there are sticks
+        let mut game = new_game(vec![2, 2], Ruleset::Lasker);
+        game.piles = vec![1; 5];
+        assert_rejected(&game);
+
+        assert_rejected(&new_game(vec![3, 4, 5], Ruleset::Wythoff));
+    }
+
+    #[test]
+    fn check_rejects_a_selection_off_the_piles() {
+        let mut game = played_game();
+        game.selected_pile = 3;
+        assert_rejected(&game);
+
+        let mut game = played_game();
+        game.paired_pile = Some(3);
+        assert_rejected(&game);
+
+        let mut game = played_game();
+        game.amount_selected = Some(5);
+        assert_rejected(&game);
+
+        for split in [0, 4, 5] {
+            let mut game = new_game(vec![4], Ruleset::Lasker);
+            game.split_selected = Some(split);
+            assert_rejected(&game);
+        }
+    }
+
+    #[test]
+    fn check_rejects_bad_subtraction_sets() {
+        let ruleset = |set| Ruleset::Subtraction(Subtraction { set });
+        new_game(vec![3, 4, 5], ruleset(vec![1, 3, 4]))
+            .validate()
+            .unwrap();
+        for set in [
+            vec![],
+            vec![4, 1],
+            vec![1, 1],
+            vec![0, 1],
+            vec![1, MAX_PILE_SIZE + 1],
+        ] {
+            assert_rejected(&new_game(vec![3, 4, 5], ruleset(set)));
+        }
+    }
+
+    #[test]
+    fn check_rejects_bad_clocks() {
+        let mut game = played_game();
+        game.timed_out = Some(game.current_player.clone());
+        assert_rejected(&game);
+
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        game.time_control = TimeControl::PerMove(30);
+        game.clock = Clock::new(&game.time_control);
+        game.validate().unwrap();
+        game.clock.host += Duration::from_secs(1);
+        assert_rejected(&game);
+    }
+
+    #[test]
+    fn validate_rejects_local_games() {
+        let mut game = played_game();
+        game.hot_seat = true;
+        game.check().unwrap();
+        assert!(game.validate().is_err());
+    }
+
+    #[test]
+    fn play_move_refuses_moves_out_of_turn() {
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        let mut waiting = game.current_player.clone();
+        waiting.flip();
+        let result = game.play_move(&Move::new(0, 1), &waiting);
+        assert!(matches!(result, Err(MoveError::NotYourTurn)));
+        assert_eq!(game.piles, [3, 4, 5]);
+    }
+
+    #[test]
+    fn play_move_refuses_illegal_moves() {
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        let player = game.current_player.clone();
+        for mv in [Move::new(0, 4), Move::new(3, 1), Move::new(1, 0)] {
+            let result = game.play_move(&mv, &player);
+            assert!(matches!(result, Err(MoveError::Illegal(refused)) if refused == mv));
+        }
+        assert_eq!(game.piles, [3, 4, 5]);
+        assert_eq!(game.moves_played, 0);
+    }
+
+    #[test]
+    fn play_move_refuses_moves_after_the_game_is_over() {
+        let mut game = new_game(vec![3], Ruleset::Nim);
+        play(&mut game, Move::new(0, 3));
+        let player = game.current_player.clone();
+        let result = game.play_move(&Move::new(0, 1), &player);
+        assert!(matches!(result, Err(MoveError::GameOver)));
+
+        let mut game = new_game(vec![3, 4, 5], Ruleset::Nim);
+        game.timed_out = Some(game.current_player.clone());
+        let player = game.current_player.clone();
+        let result = game.play_move(&Move::new(0, 1), &player);
+        assert!(matches!(result, Err(MoveError::GameOver)));
+        assert_eq!(game.piles, [3, 4, 5]);
+    }
+
+    #[test]
+    fn summarized_piles_are_counted_when_there_are_many() {
+        assert_eq!(summarize_piles(&[3, 0, 5]), "3 0 5");
+        assert_eq!(summarize_piles(&[1, 2, 3, 4, 5]), "1 2 3 4 5");
+        assert_eq!(summarize_piles(&[1; 6]), "6 piles");
+    }
+
+    #[test]
+    fn every_ruleset_is_named() {
+        assert_eq!(Ruleset::NAMES.len(), Ruleset::ALL.len());
+        for (ruleset, name) in Ruleset::ALL.iter().zip(Ruleset::NAMES) {
+            assert_eq!(ruleset.to_string(), name);
+        }
+        let subtraction = Ruleset::Subtraction(Subtraction { set: vec![1, 3, 4] });
+        assert_eq!(subtraction.to_string(), "Subtraction");
+    }
+}
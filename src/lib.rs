@@ -1,102 +1,186 @@
-use std::sync::{Arc, Mutex};
-
-use comms::{
-    client::Client,
-    server::{Server, ServerMessage},
-};
+use ai::Bot;
 use crossterm::event::KeyCode;
-use game::GameState;
+use game::{AfterGame, GameState};
+use network::{Event, NetworkGame};
+use replay::Replay;
 use tui::{backend::Backend, Frame};
 use ui::{
-    menu::{MenuState, MenuStateTransition},
+    menu::{GameMode, MenuState, MenuStateTransition},
     popup::Popup,
 };
 
+pub mod ai;
+pub mod chat;
 pub mod comms;
 pub mod game;
+pub mod network;
+pub mod replay;
+pub mod saves;
 pub mod ui;
 
 pub struct App {
     pub state: AppState,
+    /// Message shown over the current screen until the next key press.
+    pub notice: Option<String>,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum AppState {
     Menu(MenuState),
-    Game(Arc<Mutex<GameState>>, Arc<Server>, Option<Client>),
+    Game(NetworkGame),
+    Computer(GameState, Bot),
+    /// Both players sharing this machine.
+    Local(GameState),
+    /// Stepping through a finished game.
+    Replay(Replay),
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
     pub fn new() -> Self {
         Self {
             state: AppState::Menu(MenuState::MainMenu { selected: None }),
+            notice: None,
         }
     }
 
     pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>) {
         match &mut self.state {
             AppState::Menu(menu_state) => menu_state.render(frame),
-            AppState::Game(game, server, client) => {
-                if client.is_none() {
-                    Popup::new(
-                        "Waiting for connection".into(),
-                        format!("Opponent should connect to ip {}", server.url),
-                    )
-                    .render(frame);
-                    return;
-                }
-                game.lock().unwrap().render(frame);
-            }
+            AppState::Game(game) => game.render(frame),
+            AppState::Computer(game, _) | AppState::Local(game) => game.render(frame),
+            AppState::Replay(replay) => replay.render(frame),
+        }
+
+        if let Some(notice) = &self.notice {
+            Popup::new("Notice".into(), notice.clone()).render(frame);
         }
     }
 
     pub async fn handle_key(&mut self, key: KeyCode) {
-        match &mut self.state {
-            AppState::Menu(menu_state) => match menu_state.handle_key(key).await {
-                MenuStateTransition::GameOpen(server, game) => {
-                    self.state = AppState::Game(game, server, None);
-                }
-                MenuStateTransition::ConnectedToPeer(server, game, clt) => {
-                    self.state = AppState::Game(game, server, Some(clt));
-                }
-                MenuStateTransition::Continue => {}
-            },
-            AppState::Game(game, _, client) => {
-                if client.is_none() {
-                    // waiting for connection
-                    return;
-                }
-
-                let mut game_state = game.lock().unwrap();
+        if self.notice.take().is_some() {
+            return;
+        }
 
-                if game_state.handle_key(key) {
-                    client
-                        .as_ref()
-                        .unwrap()
-                        .send_game_state(&game_state)
-                        .await
-                        .unwrap();
+        let event = match &mut self.state {
+            AppState::Menu(menu_state) => {
+                match menu_state.handle_key(key).await {
+                    MenuStateTransition::NetworkGame(game) => {
+                        self.state = AppState::Game(game);
+                    }
+                    MenuStateTransition::ComputerGame(mut game) => {
+                        game.start_clock();
+                        self.state = AppState::Computer(game, Bot::new());
+                    }
+                    MenuStateTransition::LocalGame(mut game) => {
+                        game.start_clock();
+                        self.state = AppState::Local(game);
+                    }
+                    MenuStateTransition::Replay(game) => {
+                        self.state = AppState::Replay(Replay::new(game));
+                    }
+                    MenuStateTransition::Continue => {}
                 }
+                None
             }
+            AppState::Game(game) => game.handle_key(key),
+            AppState::Computer(game, _) => play_locally(game, key, GameMode::Computer),
+            AppState::Local(game) => play_locally(game, key, GameMode::Local),
+            AppState::Replay(replay) => replay.handle_key(key),
+        };
+
+        if let Some(event) = event {
+            self.handle_event(event);
         }
     }
 
     pub async fn on_tick(&mut self) {
         match &mut self.state {
-            AppState::Game(_, server, client) => {
-                if client.is_none() {
-                    if let Some(ServerMessage::GuestConnected(url)) =
-                        server.messages.lock().unwrap().pop_front()
-                    {
-                        let clt = Client::new(url);
-                        if let Err(e) = clt.check_connection().await {
-                            println!("error connecting to client: {e}");
-                            return;
-                        }
-                        *client = Some(clt);
-                    }
+            AppState::Game(game) => {
+                if let Some(event) = game.on_tick().await {
+                    self.handle_event(event);
                 }
             }
-            _ => {}
+            AppState::Menu(menu_state) => menu_state.on_tick(),
+            AppState::Computer(game, bot) => {
+                game.check_time();
+                bot.on_tick(game);
+            }
+            AppState::Local(game) => {
+                game.check_time();
+            }
+            AppState::Replay(_) => {}
+        }
+
+        let recorded = match &mut self.state {
+            AppState::Game(game) => game.record_finished(),
+            AppState::Computer(game, _) | AppState::Local(game) => saves::record_finished(game),
+            AppState::Menu(_) | AppState::Replay(_) => Ok(()),
+        };
+        if let Err(e) = recorded {
+            self.notice = Some(format!("Failed to record the replay: {e}"));
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Notice(notice) => self.notice = Some(notice),
+            Event::Ended(reason) => {
+                self.notice = reason;
+                self.state = AppState::Menu(MenuState::MainMenu { selected: None });
+            }
+            Event::NewSettings(mode) => {
+                self.state = AppState::Menu(MenuState::game_settings(mode));
+            }
+        }
+    }
+}
+
+/// Handles a key in a game played only on this machine.
+fn play_locally(game: &mut GameState, key: KeyCode, mode: GameMode) -> Option<Event> {
+    if undo_redo(game, key, matches!(mode, GameMode::Computer)) {
+        return None;
+    }
+    if let KeyCode::Char('s' | 'S') = key {
+        return Some(Event::Notice(match saves::save(game) {
+            Ok(path) => format!("Game saved to {}", path.display()),
+            Err(e) => format!("Failed to save the game: {e}"),
+        }));
+    }
+    if game.is_game_over() {
+        return after_game(game, key, mode);
+    }
+    game.handle_key(key);
+    None
+}
+
+/// Takes back a move with U, or plays it again with R, returns whether the key was one of them.
+/// Against the computer whole turns are taken back, so it is the player's move again.
+fn undo_redo(game: &mut GameState, key: KeyCode, vs_computer: bool) -> bool {
+    let step = match key {
+        KeyCode::Char('u' | 'U') => GameState::undo,
+        KeyCode::Char('r' | 'R') => GameState::redo,
+        _ => return false,
+    };
+    while step(game) && vs_computer && game.current_player != game.player_type {}
+    game.start_clock();
+    true
+}
+
+/// Handles the choice made once a game played only on this machine is over.
+fn after_game(game: &mut GameState, key: KeyCode, mode: GameMode) -> Option<Event> {
+    match AfterGame::from_key(key)? {
+        AfterGame::Rematch => {
+            *game = game.rematch();
+            game.start_clock();
+            None
         }
+        AfterGame::NewSettings => Some(Event::NewSettings(mode)),
+        AfterGame::MainMenu => Some(Event::Ended(None)),
     }
 }
@@ -18,6 +18,14 @@ async fn main() {
     // initialize tracing
     tracing_subscriber::fmt::init();
 
+    // restore the terminal before a panic message gets printed
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture);
+        default_hook(info);
+    }));
+
     // setup terminal
     enable_raw_mode().unwrap();
     let mut stdout = io::stdout();
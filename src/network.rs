@@ -0,0 +1,618 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::KeyCode;
+use tokio::task::JoinHandle;
+use tui::{backend::Backend, Frame};
+
+use crate::{
+    chat::{Chat, ChatInput, ChatLine},
+    comms::{
+        client,
+        server::{Server, ServerMessage},
+        Connection, Error, JoinAs, Message, SeatToken,
+    },
+    game::{AfterGame, GameState, GAME_OVER_KEYS},
+    saves,
+    ui::{menu::GameMode, popup::Popup},
+};
+
+/// How long a lost opponent has to come back before the game is abandoned.
+const RECONNECT_GRACE: Duration = Duration::from_secs(30);
+
+/// Time between the guest's attempts to reach the host again.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What the app should do after the networked game handled an input or a tick.
+pub enum Event {
+    /// Something to tell the player about.
+    Notice(String),
+    /// The game was left, with why if the player didn't choose to.
+    Ended(Option<String>),
+    /// The game was left to set up a new one.
+    NewSettings(GameMode),
+}
+
+enum Role {
+    Host {
+        server: Arc<Server>,
+        spectators: Vec<Connection>,
+        /// Moves played when the game was last sent to the spectators.
+        moves_broadcast: u64,
+    },
+    /// Joined someone else's game, as a player or a spectator.
+    Remote {
+        host_addr: String,
+        join_as: JoinAs,
+        /// Token to take our seat back with when reconnecting, unless spectating.
+        seat: Option<SeatToken>,
+        reconnecting: Option<JoinHandle<Result<Joined, Error>>>,
+    },
+}
+
+/// What `client::connect` returns: the game, the connection to play it through,
+/// and the token of the seat taken.
+type Joined = (GameState, Connection, Option<SeatToken>);
+
+/// A game played against someone over the network.
+pub struct NetworkGame {
+    game: Arc<Mutex<GameState>>,
+    role: Role,
+    /// Connection to the other player, or to the host when spectating.
+    /// Missing until the guest joins and while the other side is gone.
+    connection: Option<Connection>,
+    /// When the opponent was lost, while waiting for them to reconnect.
+    disconnected_since: Option<Instant>,
+    /// Whether the other seat is taken, as told by a lobby. Always the case between peers.
+    opponent_present: bool,
+    /// Address of the lobby the game is played through, if any.
+    lobby: Option<String>,
+    /// Whether we asked for a rematch, and whether the opponent did, once the game is over.
+    rematch_asked: bool,
+    rematch_offered: bool,
+}
+
+impl NetworkGame {
+    /// A hosted game, waiting for the guest to connect to the server.
+    pub fn host(server: Arc<Server>) -> Self {
+        server.current_game_state.lock().unwrap().chat = Some(Chat::new(false));
+        Self {
+            game: server.current_game_state.clone(),
+            role: Role::Host {
+                server,
+                spectators: Vec::new(),
+                moves_broadcast: 0,
+            },
+            connection: None,
+            disconnected_since: None,
+            opponent_present: true,
+            lobby: None,
+            rematch_asked: false,
+            rematch_offered: false,
+        }
+    }
+
+    /// A game joined through `connection`, as returned by `client::connect`.
+    pub fn remote(
+        host_addr: String,
+        join_as: JoinAs,
+        mut game: GameState,
+        connection: Connection,
+        seat: Option<SeatToken>,
+    ) -> Self {
+        game.chat = Some(Chat::new(join_as == JoinAs::Spectator));
+        Self {
+            game: Arc::new(Mutex::new(game)),
+            role: Role::Remote {
+                host_addr,
+                join_as,
+                seat,
+                reconnecting: None,
+            },
+            connection: Some(connection),
+            disconnected_since: None,
+            opponent_present: true,
+            lobby: None,
+            rematch_asked: false,
+            rematch_offered: false,
+        }
+    }
+
+    /// Game `id` of the lobby at `lobby_addr`, joined through `connection`.
+    pub fn joined_lobby(
+        lobby_addr: &str,
+        id: u32,
+        join_as: JoinAs,
+        game: GameState,
+        connection: Connection,
+        seat: Option<SeatToken>,
+    ) -> Self {
+        let mut game = Self::remote(
+            format!("{lobby_addr}/games/{id}"),
+            join_as,
+            game,
+            connection,
+            seat,
+        );
+        game.lobby = Some(lobby_addr.into());
+        game
+    }
+
+    /// A game just opened in the lobby at `lobby_addr` through `connection`,
+    /// as returned by `client::create_game`, waiting for someone to join it.
+    pub fn created(
+        lobby_addr: &str,
+        id: u32,
+        game: GameState,
+        connection: Connection,
+        seat: Option<SeatToken>,
+    ) -> Self {
+        let mut game = Self::joined_lobby(lobby_addr, id, JoinAs::Player, game, connection, seat);
+        game.opponent_present = false;
+        game
+    }
+
+    pub fn render<B: Backend>(&self, frame: &mut Frame<B>) {
+        match (&self.role, &self.connection, self.disconnected_since) {
+            (_, _, Some(since)) => {
+                self.game.lock().unwrap().render(frame);
+                let remaining = RECONNECT_GRACE.saturating_sub(since.elapsed());
+                let title = match self.role {
+                    Role::Remote {
+                        join_as: JoinAs::Spectator,
+                        ..
+                    } => "Host disconnected",
+                    _ => "Opponent disconnected",
+                };
+                Popup::new(
+                    title.into(),
+                    format!(
+                        "Waiting {}s for them to reconnect, press Enter to leave",
+                        remaining.as_secs()
+                    ),
+                )
+                .render(frame);
+            }
+            (Role::Host { server, .. }, None, None) => {
+                Popup::new(
+                    "Waiting for connection".into(),
+                    format!("Opponent should connect to ip {}", server.url),
+                )
+                .render(frame);
+            }
+            (Role::Remote { .. }, _, None) if !self.opponent_present => {
+                self.game.lock().unwrap().render(frame);
+                Popup::new(
+                    "Waiting for an opponent".into(),
+                    "Others can join from the lobby, press Enter to leave".into(),
+                )
+                .render(frame);
+            }
+            _ => self.render_game(frame),
+        }
+    }
+
+    /// Renders the game, with how the rematch is going once it is over.
+    fn render_game<B: Backend>(&self, frame: &mut Frame<B>) {
+        let game_state = self.game.lock().unwrap();
+        if game_state.spectating {
+            return game_state.render(frame);
+        }
+        let mut opponent = game_state.player_type.clone();
+        opponent.flip();
+        let opponent = game_state.names.get(&opponent);
+        let prompt = if self.rematch_asked {
+            format!("Waiting for {opponent} to accept the rematch, N: new settings, M: main menu")
+        } else if self.rematch_offered {
+            format!("{opponent} wants a rematch, Enter: accept, N: new settings, M: main menu")
+        } else {
+            GAME_OVER_KEYS.into()
+        };
+        game_state.render_ended(frame, &prompt);
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<Event> {
+        if self.disconnected_since.is_some() || !self.opponent_present {
+            return (key == KeyCode::Enter).then_some(Event::Ended(None));
+        }
+        let connection = self.connection.as_ref()?;
+
+        let chat = self.game.lock().unwrap().chat.as_mut()?.handle_key(key);
+        match chat {
+            Some(ChatInput::Handled) => return None,
+            Some(ChatInput::Send(text)) => return self.say(&text),
+            None => {}
+        }
+
+        let finished = {
+            let game_state = self.game.lock().unwrap();
+            game_state.is_game_over() && !game_state.spectating
+        };
+        if finished {
+            return self.after_game(key);
+        }
+
+        let played = {
+            let mut game_state = self.game.lock().unwrap();
+            if game_state.spectating {
+                return (key == KeyCode::Enter).then_some(Event::Ended(None));
+            }
+            let before = game_state.clone();
+            game_state.handle_key(key).map(|mv| (mv, before))
+        };
+
+        let (mv, before) = played?;
+        if let Err(e) = connection.send(Message::Move(mv)) {
+            // the opponent never got it, so it isn't played
+            *self.game.lock().unwrap() = before;
+            return Some(Event::Notice(format!("Could not play move: {e}")));
+        }
+        None
+    }
+
+    /// Sends a line to the chat, also showing it in ours.
+    fn say(&self, text: &str) -> Option<Event> {
+        let connection = self.connection.as_ref()?;
+        let mut game_state = self.game.lock().unwrap();
+        let line = ChatLine::new(game_state.names.get(&game_state.player_type), text);
+        if let Err(e) = connection.send(Message::Chat(line.clone())) {
+            return Some(Event::Notice(format!("Could not send message: {e}")));
+        }
+        if let Role::Host { spectators, .. } = &self.role {
+            for spectator in spectators {
+                let _ = spectator.send(Message::Chat(line.clone()));
+            }
+        }
+        game_state.chat.as_mut()?.push(line);
+        None
+    }
+
+    /// Handles the choice made once the game is over.
+    fn after_game(&mut self, key: KeyCode) -> Option<Event> {
+        let connection = self.connection.as_ref()?;
+        let choice = AfterGame::from_key(key)?;
+        match choice {
+            AfterGame::Rematch if self.rematch_asked => None,
+            AfterGame::Rematch if self.rematch_offered && self.is_host() => {
+                self.start_rematch();
+                None
+            }
+            AfterGame::Rematch => match connection.send(Message::Rematch) {
+                Ok(()) => {
+                    self.rematch_asked = true;
+                    None
+                }
+                Err(e) => Some(Event::Notice(format!("Could not ask for a rematch: {e}"))),
+            },
+            AfterGame::NewSettings | AfterGame::MainMenu => {
+                let _ = connection.send(Message::Left);
+                if let Role::Host { spectators, .. } = &self.role {
+                    for spectator in spectators {
+                        let _ = spectator.send(Message::Left);
+                    }
+                }
+                Some(match choice {
+                    AfterGame::NewSettings => Event::NewSettings(match &self.lobby {
+                        Some(addr) => GameMode::Lobby(addr.clone()),
+                        None => GameMode::Network,
+                    }),
+                    _ => Event::Ended(None),
+                })
+            }
+        }
+    }
+
+    /// Starts a new game with the same settings, once both players agreed to it.
+    /// Only the host does, the guest gets the new game from it.
+    fn start_rematch(&mut self) {
+        let mut game_state = self.game.lock().unwrap();
+        *game_state = game_state.rematch();
+        if let Some(connection) = &self.connection {
+            let _ = connection.send(Message::Game(game_state.for_opponent()));
+        }
+        if let Role::Host {
+            moves_broadcast, ..
+        } = &mut self.role
+        {
+            // makes sure the spectators get the new game
+            *moves_broadcast = u64::MAX;
+        }
+    }
+
+    fn is_host(&self) -> bool {
+        matches!(self.role, Role::Host { .. })
+    }
+
+    /// Writes the game to a replay file once it is over, see `saves::record_finished`.
+    pub fn record_finished(&self) -> Result<(), saves::Error> {
+        saves::record_finished(&mut self.game.lock().unwrap())
+    }
+
+    pub async fn on_tick(&mut self) -> Option<Event> {
+        {
+            let mut game_state = self.game.lock().unwrap();
+            if !game_state.is_game_over() {
+                self.rematch_asked = false;
+                self.rematch_offered = false;
+            }
+            // time only runs once both players are there
+            if self.connection.is_some() && self.opponent_present {
+                game_state.start_clock();
+            }
+        }
+
+        let mut event = None;
+        if let Role::Host { .. } = self.role {
+            event = self.host_tick();
+        }
+
+        if let Some(since) = self.disconnected_since {
+            if since.elapsed() > RECONNECT_GRACE {
+                let reason = match self.role {
+                    Role::Remote {
+                        join_as: JoinAs::Spectator,
+                        ..
+                    } => "The host did not come back",
+                    _ => "Opponent did not reconnect",
+                };
+                return Some(Event::Ended(Some(reason.into())));
+            }
+            return self.reconnect().await.or(event);
+        }
+
+        let is_host = self.is_host();
+        let mut rematch = false;
+        let Some(connection) = self.connection.as_mut() else {
+            return event;
+        };
+        loop {
+            match connection.try_recv() {
+                Ok(Some(Message::OpponentPresent(present))) => {
+                    self.opponent_present = present;
+                    if !present {
+                        self.game.lock().unwrap().stop_clock();
+                    }
+                }
+                Ok(Some(Message::Rematch)) => {
+                    rematch = is_host && self.rematch_asked;
+                    self.rematch_offered = true;
+                }
+                Ok(Some(Message::Chat(line))) => {
+                    let mut game_state = self.game.lock().unwrap();
+                    let line = if is_host {
+                        // the guest can only speak for themselves
+                        let mut guest = game_state.player_type.clone();
+                        guest.flip();
+                        let line = ChatLine::new(game_state.names.get(&guest), &line.text);
+                        if let Role::Host { spectators, .. } = &self.role {
+                            for spectator in spectators {
+                                let _ = spectator.send(Message::Chat(line.clone()));
+                            }
+                        }
+                        line
+                    } else {
+                        ChatLine::new(&line.from, &line.text)
+                    };
+                    if let Some(chat) = &mut game_state.chat {
+                        chat.push(line);
+                    }
+                }
+                Ok(Some(Message::Left)) => {
+                    let game_state = self.game.lock().unwrap();
+                    let reason = if game_state.spectating {
+                        "The players left the game".into()
+                    } else {
+                        let mut opponent = game_state.player_type.clone();
+                        opponent.flip();
+                        format!("{} left the game", game_state.names.get(&opponent))
+                    };
+                    return Some(Event::Ended(Some(reason)));
+                }
+                Ok(Some(message)) => {
+                    if let Some(notice) = handle_message(&self.game, is_host, connection, message) {
+                        event = Some(Event::Notice(notice));
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    self.connection = None;
+                    self.disconnected_since = Some(Instant::now());
+                    // nobody runs out of time while waiting for the other side to come back
+                    self.game.lock().unwrap().stop_clock();
+                    break;
+                }
+            }
+        }
+        if rematch {
+            self.start_rematch();
+        }
+        event
+    }
+
+    /// Takes in whoever joined the server, and keeps the spectators up to date.
+    fn host_tick(&mut self) -> Option<Event> {
+        let Role::Host {
+            server,
+            spectators,
+            moves_broadcast,
+        } = &mut self.role
+        else {
+            return None;
+        };
+
+        let mut event = None;
+        let messages: Vec<_> = server.messages.lock().unwrap().drain(..).collect();
+        let watching = spectators.len();
+        for message in messages {
+            match message {
+                // only the guest can take their seat back, maybe before the connection they
+                // had was found to be lost
+                ServerMessage::GuestConnected(guest) => {
+                    self.connection = Some(guest);
+                    if self.disconnected_since.take().is_some() {
+                        event = Some(Event::Notice("Opponent reconnected".into()));
+                    }
+                }
+                ServerMessage::SpectatorJoined(spectator) => spectators.push(spectator),
+                ServerMessage::JoinRefused(reason) => {
+                    event = Some(Event::Notice(format!("Refused a player: {reason}")));
+                }
+            }
+        }
+
+        // spectators have nothing to say, reading from them only tells if they're still there
+        spectators.retain_mut(|spectator| loop {
+            match spectator.try_recv() {
+                Ok(Some(_)) => {}
+                Ok(None) => break true,
+                Err(_) => break false,
+            }
+        });
+
+        let mut game_state = self.game.lock().unwrap();
+        if spectators.len() != watching {
+            game_state.spectators = spectators.len();
+            let count = Message::Spectators(spectators.len());
+            if let Some(connection) = &self.connection {
+                let _ = connection.send(count);
+            }
+        }
+        // the host's clock is the one that counts, so it decides when time runs out
+        let timed_out = game_state.check_time();
+        if timed_out {
+            if let Some(connection) = &self.connection {
+                let _ = connection.send(Message::Game(game_state.for_opponent()));
+            }
+        }
+        let moved = game_state.moves_played != *moves_broadcast;
+        if moved {
+            if let Some(connection) = &self.connection {
+                let _ = connection.send(Message::Clock(game_state.clock.clone()));
+            }
+        }
+        if moved || timed_out || spectators.len() != watching {
+            *moves_broadcast = game_state.moves_played;
+            for spectator in spectators.iter() {
+                let _ = spectator.send(Message::Game(game_state.clone()));
+            }
+        }
+        event
+    }
+
+    /// Keeps trying to reach the host again, picking the game up from where the host has it.
+    async fn reconnect(&mut self) -> Option<Event> {
+        let Role::Remote {
+            host_addr,
+            join_as,
+            seat,
+            reconnecting,
+        } = &mut self.role
+        else {
+            // the host waits for the guest to come back to the server
+            return None;
+        };
+
+        match reconnecting {
+            Some(attempt) if attempt.is_finished() => {
+                let attempt = reconnecting.take()?;
+                if let Ok(Ok((game_state, connection, token))) = attempt.await {
+                    replace_game(&mut self.game.lock().unwrap(), game_state);
+                    self.connection = Some(connection);
+                    *seat = token;
+                    self.disconnected_since = None;
+                    return Some(Event::Notice("Reconnected to the host".into()));
+                }
+            }
+            Some(_) => {}
+            None => {
+                let host_addr = host_addr.clone();
+                let join_as = *join_as;
+                let seat = *seat;
+                *reconnecting = Some(tokio::spawn(async move {
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                    client::connect(&host_addr, join_as, seat).await
+                }));
+            }
+        }
+        None
+    }
+}
+
+impl Drop for NetworkGame {
+    fn drop(&mut self) {
+        if let Role::Host { server, .. } = &self.role {
+            server.stop();
+        }
+    }
+}
+
+/// Applies a message from the opponent to our copy of the game, checking any move before
+/// playing it. Returns a notice to show, if something went wrong.
+fn handle_message(
+    game: &Mutex<GameState>,
+    is_host: bool,
+    connection: &Connection,
+    message: Message,
+) -> Option<String> {
+    let mut game_state = game.lock().unwrap();
+    match message {
+        Message::Move(mv) => {
+            let mut opponent = game_state.player_type.clone();
+            opponent.flip();
+            let e = game_state.play_move(&mv, &opponent).err()?;
+            let _ = connection.send(Message::Rejected(e.to_string()));
+            if is_host {
+                // the host's game is the one that counts, so the guest is corrected
+                let _ = connection.send(Message::Game(game_state.for_opponent()));
+            }
+            Some(format!("Opponent sent an invalid move: {e}"))
+        }
+        Message::Rejected(reason) => {
+            if is_host {
+                // the games went apart, the host's is the one the guest goes back to
+                let _ = connection.send(Message::Game(game_state.for_opponent()));
+            }
+            Some(format!("Opponent refused our move: {reason}"))
+        }
+        Message::Game(new_state) if !is_host => match new_state.validate() {
+            Ok(()) => {
+                replace_game(&mut game_state, new_state);
+                None
+            }
+            Err(e) => Some(format!("Opponent sent an invalid game: {e}")),
+        },
+        Message::Spectators(count) if !is_host => {
+            game_state.spectators = count;
+            None
+        }
+        Message::Clock(clock) if !is_host => {
+            game_state.clock = clock;
+            game_state.turn_started = None;
+            game_state.start_clock();
+            None
+        }
+        Message::Game(_)
+        | Message::Spectators(_)
+        | Message::Clock(_)
+        | Message::GameCreated(_)
+        | Message::OpponentPresent(_)
+        | Message::Rematch
+        | Message::Left
+        | Message::Chat(_)
+        | Message::Heartbeat
+        | Message::Seat(_) => None,
+    }
+}
+
+/// Replaces our copy of the game with one received from the host, keeping what is only ours.
+fn replace_game(game_state: &mut GameState, mut new_state: GameState) {
+    new_state.spectating = game_state.spectating;
+    new_state.chat = game_state.chat.take();
+    // a finished game sent again is still the one already recorded
+    new_state.recorded = game_state.recorded && new_state.is_game_over();
+    // the clock restarts from the time the host had left when sending it
+    new_state.start_clock();
+    *game_state = new_state;
+}
@@ -0,0 +1,181 @@
+use crossterm::event::KeyCode;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout},
+    widgets::Paragraph,
+    Frame,
+};
+
+use crate::{
+    ai::{winning_move, Analysis},
+    game::{GameState, Move, PlayerType, TimeControl},
+    network::Event,
+};
+
+/// Keys offered while watching a replay.
+const REPLAY_KEYS: &str = "Left/Right: step, Enter: leave";
+
+/// A finished game stepped through move by move.
+pub struct Replay {
+    game: GameState,
+    /// Number of moves played to reach the position shown.
+    shown: usize,
+    /// Move that would have kept the win, for each move that threw it away.
+    /// Moves are only analysed once they have been shown.
+    better: Vec<Option<Move>>,
+    /// Winning move from each position analysed so far, by moves played to reach it.
+    winning: Vec<Option<Move>>,
+    analysis: Analysis,
+    /// The position shown, as a game the pile renderer can draw.
+    view: GameState,
+}
+
+impl Replay {
+    pub fn new(game: GameState) -> Self {
+        let mut replay = Self {
+            view: game.clone(),
+            game,
+            shown: 0,
+            better: vec![],
+            winning: vec![],
+            analysis: Analysis::default(),
+        };
+        replay.show(0);
+        replay
+    }
+
+    /// Finds out whether the moves up to the `shown`th were mistakes, if not done yet.
+    fn analyse(&mut self, shown: usize) {
+        while self.better.len() < shown {
+            let played = self.better.len();
+            let better = self.winning(played).filter(|_| self.wins(played + 1));
+            self.better.push(better);
+        }
+    }
+
+    /// Winning move from the position after `moves` moves, each position being
+    /// analysed only once.
+    fn winning(&mut self, moves: usize) -> Option<Move> {
+        while self.winning.len() <= moves {
+            let game = &self.game;
+            let piles = piles_after(game, self.winning.len());
+            let best = winning_move(&game.ruleset, piles, game.convention, &mut self.analysis);
+            self.winning.push(best);
+        }
+        self.winning[moves]
+    }
+
+    /// Whether the player to move after `moves` moves can force a win.
+    fn wins(&mut self, moves: usize) -> bool {
+        let rules = self.game.ruleset.rules();
+        let piles = piles_after(&self.game, moves);
+        if rules.is_terminal(piles) {
+            let winner = rules.winner(piles, &PlayerType::Host, self.game.convention);
+            return winner == Some(PlayerType::Host);
+        }
+        self.winning(moves).is_some()
+    }
+
+    /// Moves to the position after `shown` moves.
+    fn show(&mut self, shown: usize) {
+        self.analyse(shown);
+        let game = &self.game;
+        let history = &game.history[..shown];
+        let mut current_player = game.first_player();
+        if shown % 2 == 1 {
+            current_player.flip();
+        }
+
+        // both sides are shown by name, and the clock isn't, as it is only known at the end
+        self.view = GameState {
+            piles: piles_after(game, shown).to_vec(),
+            history: history.to_vec(),
+            moves_played: shown as u64,
+            current_player,
+            selected_pile: 0,
+            paired_pile: None,
+            amount_selected: None,
+            split_selected: None,
+            time_control: TimeControl::Unlimited,
+            timed_out: game
+                .timed_out
+                .clone()
+                .filter(|_| shown == game.history.len()),
+            hot_seat: true,
+            ..game.clone()
+        };
+        // the move played next is shown selected
+        if let Some(next) = game.history.get(shown) {
+            self.view.select(&next.mv);
+        }
+        self.shown = shown;
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<Event> {
+        match key {
+            KeyCode::Left => self.show(self.shown.saturating_sub(1)),
+            KeyCode::Right => self.show((self.shown + 1).min(self.game.history.len())),
+            KeyCode::Enter | KeyCode::Char('m' | 'M') => return Some(Event::Ended(None)),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render<B: Backend>(&self, frame: &mut Frame<B>) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .split(frame.size());
+
+        let notes: Vec<String> = self.better[..self.shown]
+            .iter()
+            .map(|better| better.map_or_else(String::new, |_| "?".into()))
+            .collect();
+        self.view
+            .render_annotated(frame, chunks[0], REPLAY_KEYS, &notes);
+
+        let total = self.game.history.len();
+        let mut status = format!(" Replay · move {} of {total}", self.shown);
+        if let Some(Some(best)) = self.shown.checked_sub(1).map(|last| self.better[last]) {
+            status += &format!(" · a mistake, {best} would have won");
+        }
+        status += &format!(" · {REPLAY_KEYS}");
+        frame.render_widget(Paragraph::new(status), chunks[1]);
+    }
+}
+
+/// Piles of `game` after `moves` moves.
+fn piles_after(game: &GameState, moves: usize) -> &[u32] {
+    match moves.checked_sub(1) {
+        Some(last) => &game.history[last].piles,
+        None => &game.initial_piles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{PileSetup, PlayConvention, Ruleset};
+
+    #[test]
+    fn marks_the_moves_that_threw_the_win_away() {
+        let mut game = GameState::new(
+            &PileSetup::Exact(vec![1, 2]),
+            PlayerType::Host,
+            Ruleset::Nim,
+            PlayConvention::Normal,
+            None,
+            TimeControl::Unlimited,
+        );
+        game.hot_seat = true;
+        // taking the single stick leaves 2, which wins for the opponent, 2-1 would have won
+        for mv in [Move::new(0, 1), Move::new(1, 2)] {
+            let player = game.current_player.clone();
+            game.play_move(&mv, &player).unwrap();
+        }
+
+        let mut replay = Replay::new(game);
+        replay.show(2);
+        assert_eq!(replay.better, [Some(Move::new(1, 1)), None]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::game::GameState;
+
+/// Why a game couldn't be saved or loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// There is no directory to keep user data in on this system.
+    NoDataDir,
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The file holds a game that can't be played.
+    InvalidGame(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoDataDir => f.write_str("no user data directory"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "{e}"),
+            Self::InvalidGame(e) => write!(f, "invalid game: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// What is kept on disk, games to be resumed or finished games to be replayed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Save,
+    Replay,
+}
+
+impl Kind {
+    /// Directory the files are kept in, inside the user data directory.
+    pub fn dir(&self) -> Result<PathBuf, Error> {
+        let data_dir = dirs::data_dir().ok_or(Error::NoDataDir)?;
+        Ok(data_dir.join("term-nim").join(match self {
+            Self::Save => "saves",
+            Self::Replay => "replays",
+        }))
+    }
+}
+
+/// A game saved to disk, along with where it was saved.
+pub struct Save {
+    pub path: PathBuf,
+    pub game: GameState,
+}
+
+impl Save {
+    /// Reads and checks the game saved at `path`.
+    pub fn load(path: PathBuf) -> Result<Self, Error> {
+        let game: GameState = serde_json::from_slice(&fs::read(&path)?)?;
+        game.check().map_err(Error::InvalidGame)?;
+        Ok(Self { path, game })
+    }
+}
+
+impl Display for Save {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let game = &self.game;
+        write!(
+            f,
+            "{} vs {} - {} ({})",
+            game.names.host, game.names.guest, game.ruleset, game.convention,
+        )?;
+        match game.winner() {
+            Some(winner) => write!(
+                f,
+                " - {} moves, {} won",
+                game.moves_played,
+                game.names.get(&winner)
+            ),
+            None => write!(f, " - move {}", game.moves_played + 1),
+        }
+    }
+}
+
+/// Saves a game to a new file, returns its path.
+pub fn save(game: &GameState) -> Result<PathBuf, Error> {
+    write(Kind::Save, game)
+}
+
+/// Writes a finished game to a new replay file, the first time it is seen over.
+pub fn record_finished(game: &mut GameState) -> Result<(), Error> {
+    if !game.is_game_over() || game.recorded {
+        return Ok(());
+    }
+    game.recorded = true;
+    write(Kind::Replay, game).map(|_| ())
+}
+
+fn write(kind: Kind, game: &GameState) -> Result<PathBuf, Error> {
+    let dir = kind.dir()?;
+    fs::create_dir_all(&dir)?;
+
+    // the time spent on the current turn is kept, the clock starts again once loaded
+    let mut game = game.clone();
+    game.stop_clock();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("{now}.json"));
+    fs::write(&path, serde_json::to_vec(&game)?)?;
+    Ok(path)
+}
+
+/// Lists the games kept on disk, the latest first. Files that can't be read are left out.
+pub fn list(kind: Kind) -> Result<Vec<Save>, Error> {
+    let dir = kind.dir()?;
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| Save::load(path).ok())
+        .collect())
+}
@@ -6,6 +6,7 @@ use tui::{
     Frame,
 };
 
+#[derive(Clone)]
 pub struct StringForm {
     label: String,
     expected_input_size: u16,
@@ -23,10 +24,16 @@ impl StringForm {
 
     pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>) {
         let screen = frame.size();
+        self.render_in(frame, screen);
+    }
 
+    /// Renders the form centered in `area` instead of the whole screen.
+    pub fn render_in<B: Backend>(&self, frame: &mut Frame<B>, area: Rect) {
         let inner = super::utils::get_center_of_rect_for_rect(
-            &screen,
-            (self.state.len() as u16 + 5).max(self.expected_input_size),
+            &area,
+            (self.state.len() as u16 + 5)
+                .max(self.expected_input_size)
+                .min(area.width),
             7,
         );
 
@@ -42,11 +49,15 @@ impl StringForm {
             x: inner.x + 2,
             // center height
             y: inner.y + inner.height / 2,
-            width: inner.width - 3,
-            height: inner.height,
+            width: inner.width.saturating_sub(3),
+            // what wraps stays inside the border
+            height: (inner.height - inner.height / 2).saturating_sub(1),
         };
         frame.render_widget(popup_text, shifted_inner);
-        frame.set_cursor(shifted_inner.x + self.state.len() as u16, shifted_inner.y)
+        frame.set_cursor(
+            shifted_inner.x + (self.state.len() as u16).min(shifted_inner.width),
+            shifted_inner.y,
+        )
     }
 
     pub fn handle_key(&mut self, key: KeyCode) {
@@ -1,4 +1,6 @@
 use std::{
+    env,
+    fmt::{self, Display},
     net::{IpAddr, Ipv4Addr},
     sync::{Arc, Mutex},
 };
@@ -15,63 +17,429 @@ use tui::{
 };
 
 use crate::{
-    comms::{client::Client, server::Server},
-    game::{GameState, PileAmount, PileSize, PlayerType},
+    ai::Difficulty,
+    comms::{
+        client,
+        discovery::{Announcement, Browser},
+        lobby::{Listing, LOBBY_PORT},
+        player_name,
+        server::Server,
+        JoinAs,
+    },
+    game::{
+        GameState, PileAmount, PileSetup, PileSize, PlayConvention, PlayerType, Ruleset,
+        Subtraction, TimeControl,
+    },
+    network::NetworkGame,
+    saves::{self, Kind, Save},
 };
 
 use super::{form::StringForm, stateful_list::StatefulList, utils::get_center_of_rect_for_text};
 
+const MAIN_MENU_ENTRIES: [&str; 6] = [
+    "Create Game",
+    "Connect to Game",
+    "Play vs Computer",
+    "Local 2 Players",
+    "Load Game",
+    "Online Lobby",
+];
+
+/// Settings that are typed in, asked for once the game settings are confirmed.
+#[derive(Clone, Copy)]
+pub enum SetupForm {
+    Piles,
+    Subtraction,
+}
+
+/// Who the game being set up is played against.
+#[derive(Clone, PartialEq, Eq)]
+pub enum GameMode {
+    Network,
+    Computer,
+    /// Both players on this machine, taking turns at the keyboard.
+    Local,
+    /// Opened in the lobby at the given address.
+    Lobby(String),
+}
+
+/// What can be picked in the lobby screen.
+#[derive(Clone, PartialEq, Eq)]
+pub enum LobbyEntry {
+    Refresh,
+    Create,
+    Game(Listing),
+}
+
+impl Display for LobbyEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Refresh => f.write_str("Refresh"),
+            Self::Create => f.write_str("Create a new game"),
+            Self::Game(game) => write!(f, "{game}"),
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 pub enum MenuState {
     MainMenu {
-        selected: Option<bool>,
+        selected: Option<usize>,
     },
     GameSettings {
-        selected: Option<bool>,
+        mode: GameMode,
+        selected: Option<usize>,
         amounts: StatefulList<PileAmount>,
         sizes: StatefulList<PileSize>,
+        variants: StatefulList<Ruleset>,
+        conventions: StatefulList<PlayConvention>,
+        time_controls: StatefulList<TimeControl>,
+        difficulties: StatefulList<Difficulty>,
+        form: Option<(SetupForm, StringForm)>,
+        custom_piles: Option<PileSetup>,
+        subtraction: Option<Subtraction>,
     },
     ConnectToPeer {
         form: StringForm,
-        client: Option<Client>,
+        /// Games found on the local network, picking one fills in its address.
+        games: StatefulList<Announcement>,
+        /// `None` when the discovery port couldn't be listened on.
+        browser: Option<Browser>,
     },
     WaitingForConnection {
         form: StringForm,
         game: GameState,
     },
+    /// Games open on a lobby server, see `comms::lobby`.
+    Lobby {
+        form: StringForm,
+        entries: StatefulList<LobbyEntry>,
+        /// What happened last, shown as the title of the list.
+        status: String,
+    },
+    /// Games saved to disk, or the replays of finished ones, see `saves`.
+    LoadGame {
+        kind: Kind,
+        saves: StatefulList<Save>,
+        status: String,
+    },
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum MenuStateTransition {
     Continue,
-    GameOpen(Arc<Server>, Arc<Mutex<GameState>>),
-    ConnectedToPeer(Arc<Server>, Arc<Mutex<GameState>>, Client),
+    NetworkGame(NetworkGame),
+    ComputerGame(GameState),
+    LocalGame(GameState),
+    /// A finished game to step through.
+    Replay(GameState),
 }
 
 impl MenuState {
+    pub fn game_settings(mode: GameMode) -> Self {
+        MenuState::GameSettings {
+            mode,
+            selected: None,
+            amounts: StatefulList::with_items(vec![
+                PileAmount::Two,
+                PileAmount::Five,
+                PileAmount::Ten,
+                PileAmount::Custom,
+            ]),
+            sizes: StatefulList::with_items(vec![
+                PileSize::Small,
+                PileSize::Medium,
+                PileSize::Large,
+            ]),
+            variants: StatefulList::with_items(vec![
+                Ruleset::Nim,
+                Ruleset::Subtraction(Subtraction { set: vec![1, 3, 4] }),
+                Ruleset::Wythoff,
+                Ruleset::Lasker,
+                Ruleset::Kayles,
+            ]),
+            conventions: StatefulList::with_items(vec![
+                PlayConvention::Normal,
+                PlayConvention::Misere,
+            ]),
+            time_controls: StatefulList::with_items(vec![
+                TimeControl::Unlimited,
+                TimeControl::PerMove(15),
+                TimeControl::PerMove(30),
+                TimeControl::Clock {
+                    total: 180,
+                    increment: 2,
+                },
+                TimeControl::Clock {
+                    total: 300,
+                    increment: 5,
+                },
+            ]),
+            difficulties: StatefulList::with_items(vec![
+                Difficulty::Random,
+                Difficulty::Easy,
+                Difficulty::Medium,
+                Difficulty::Perfect,
+            ]),
+            form: None,
+            custom_piles: None,
+            subtraction: None,
+        }
+    }
+
+    fn lobby(addr: String) -> Self {
+        MenuState::Lobby {
+            form: StringForm::new("Lobby address (Tab to watch)".into(), 34, Some(addr)),
+            entries: StatefulList::with_items(vec![LobbyEntry::Refresh, LobbyEntry::Create]),
+            status: "Pick Refresh to list the games".into(),
+        }
+    }
+
+    fn load_game(kind: Kind) -> Self {
+        let (one, name, other) = match kind {
+            Kind::Save => ("saved game", "saved games", "Tab: replays"),
+            Kind::Replay => ("replay", "replays", "Tab: saved games"),
+        };
+        let (saves, status) = match saves::list(kind) {
+            Ok(saves) => {
+                let status = match saves.len() {
+                    0 => format!("No {name} yet ({other})"),
+                    1 => format!("1 {one} ({other})"),
+                    n => format!("{n} {name} ({other})"),
+                };
+                (saves, status)
+            }
+            Err(e) => (vec![], format!("Failed to list the {name}: {e} ({other})")),
+        };
+        MenuState::LoadGame {
+            kind,
+            saves: StatefulList::with_items(saves),
+            status,
+        }
+    }
+
+    fn game_settings_titles(mode: &GameMode) -> &'static [&'static str] {
+        match mode {
+            GameMode::Network | GameMode::Local | GameMode::Lobby(_) => &[
+                "Pile Amount",
+                "Pile Size",
+                "Variant",
+                "Convention",
+                "Time Control",
+            ],
+            GameMode::Computer => &[
+                "Pile Amount",
+                "Pile Size",
+                "Variant",
+                "Convention",
+                "Time Control",
+                "Difficulty",
+            ],
+        }
+    }
+
+    /// Asks for the typed in settings the selected ones still need, then starts the game.
+    async fn continue_setup(&mut self) -> MenuStateTransition {
+        let MenuState::GameSettings {
+            amounts,
+            variants,
+            form,
+            custom_piles,
+            subtraction,
+            ..
+        } = self
+        else {
+            return MenuStateTransition::Continue;
+        };
+
+        if custom_piles.is_none() && matches!(amounts.get_selected(), Some(PileAmount::Custom)) {
+            *form = Some((
+                SetupForm::Piles,
+                StringForm::new(
+                    "Pile sizes (3 4 5) or count x max size (7x20)".into(),
+                    50,
+                    None,
+                ),
+            ));
+            return MenuStateTransition::Continue;
+        }
+
+        if let (None, Some(Ruleset::Subtraction(default))) = (&subtraction, variants.get_selected())
+        {
+            let set = default.to_string();
+            *form = Some((
+                SetupForm::Subtraction,
+                StringForm::new("Amounts that can be taken".into(), 30, Some(set)),
+            ));
+            return MenuStateTransition::Continue;
+        }
+
+        self.start_game().await
+    }
+
+    /// Handles the confirmation of a setup form, validating what was typed in.
+    async fn submit_setup_form(&mut self) -> MenuStateTransition {
+        let MenuState::GameSettings {
+            variants,
+            form: Some((kind, form)),
+            custom_piles,
+            subtraction,
+            ..
+        } = self
+        else {
+            return MenuStateTransition::Continue;
+        };
+
+        let input = form.consume();
+        let result = match kind {
+            SetupForm::Piles => input.parse::<PileSetup>().and_then(|setup| {
+                match variants.get_selected().and_then(Ruleset::fixed_pile_amount) {
+                    Some(amount) if amount != setup.pile_amount() => Err(format!(
+                        "{} is played on {amount} piles",
+                        variants.get_selected().unwrap()
+                    )),
+                    _ => {
+                        *custom_piles = Some(setup);
+                        Ok(())
+                    }
+                }
+            }),
+            SetupForm::Subtraction => input.parse().map(|set| *subtraction = Some(set)),
+        };
+
+        if let Err(e) = result {
+            form.state = e;
+            return MenuStateTransition::Continue;
+        }
+
+        if let MenuState::GameSettings { form, .. } = self {
+            *form = None;
+        }
+        self.continue_setup().await
+    }
+
+    /// Leaves the game settings screen, starting a game with the selected settings.
+    async fn start_game(&mut self) -> MenuStateTransition {
+        let MenuState::GameSettings {
+            mode,
+            amounts,
+            sizes,
+            variants,
+            conventions,
+            time_controls,
+            difficulties,
+            custom_piles,
+            subtraction,
+            ..
+        } = self
+        else {
+            return MenuStateTransition::Continue;
+        };
+
+        let ruleset = match subtraction.take() {
+            Some(subtraction) => Ruleset::Subtraction(subtraction),
+            None => variants.get_selected().unwrap().clone(),
+        };
+        let setup = custom_piles.take().unwrap_or_else(|| PileSetup::Random {
+            amount: ruleset
+                .fixed_pile_amount()
+                .or(amounts.get_selected().unwrap().amount())
+                .unwrap(),
+            max_size: sizes.get_selected().unwrap().quantity_limit(),
+        });
+
+        let difficulty = match mode {
+            GameMode::Network | GameMode::Local | GameMode::Lobby(_) => None,
+            GameMode::Computer => difficulties.get_selected().copied(),
+        };
+        let mut game = GameState::new(
+            &setup,
+            PlayerType::Host,
+            ruleset,
+            *conventions.get_selected().unwrap(),
+            difficulty,
+            *time_controls.get_selected().unwrap(),
+        );
+        game.names.set(&PlayerType::Host, &player_name());
+
+        if *mode == GameMode::Local {
+            game.names.set(&PlayerType::Host, "Player 1");
+            game.names.set(&PlayerType::Guest, "Player 2");
+            game.hot_seat = true;
+            return MenuStateTransition::LocalGame(game);
+        }
+
+        if *mode == GameMode::Computer {
+            game.names.set(&PlayerType::Guest, "Computer");
+            return MenuStateTransition::ComputerGame(game);
+        }
+
+        if let GameMode::Lobby(addr) = mode {
+            let addr = addr.clone();
+            return match client::create_game(&addr, &game).await {
+                Ok((id, connection, seat)) => MenuStateTransition::NetworkGame(
+                    NetworkGame::created(&addr, id, game, connection, seat),
+                ),
+                Err(e) => {
+                    *self = MenuState::lobby(addr);
+                    if let MenuState::Lobby { status, .. } = self {
+                        *status = format!("Failed to create the game: {e}");
+                    }
+                    MenuStateTransition::Continue
+                }
+            };
+        }
+
+        let default_addr = format!(
+            "{}:4088",
+            local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        *self = MenuState::WaitingForConnection {
+            form: StringForm::new(
+                "IP to expose".into(),
+                default_addr.len() as u16,
+                Some(default_addr),
+            ),
+            game,
+        };
+        MenuStateTransition::Continue
+    }
+
+    /// Refreshes the games found on the local network.
+    pub fn on_tick(&mut self) {
+        if let MenuState::ConnectToPeer {
+            games,
+            browser: Some(browser),
+            ..
+        } = self
+        {
+            games.set_items(browser.games());
+        }
+    }
+
     pub fn render<B: Backend>(&mut self, frame: &mut Frame<B>) {
         match self {
             MenuState::MainMenu { selected } => {
                 let chunks = Layout::default()
                     .direction(Direction::Horizontal)
                     .margin(2)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                    .constraints(
+                        MAIN_MENU_ENTRIES
+                            .iter()
+                            .map(|_| Constraint::Ratio(1, MAIN_MENU_ENTRIES.len() as u32))
+                            .collect::<Vec<_>>(),
+                    )
                     .split(frame.size());
 
                 let simple_block = Block::default().borders(Borders::ALL);
 
-                frame.render_widget(simple_block.clone(), chunks[0]);
-                frame.render_widget(simple_block.clone(), chunks[1]);
-
-                let create_game_text = Paragraph::new("Create Game");
-                let connect_to_game_text = Paragraph::new("Connect to Game");
-
-                frame.render_widget(
-                    create_game_text,
-                    get_center_of_rect_for_text(&chunks[0], "Create Game"),
-                );
-                frame.render_widget(
-                    connect_to_game_text,
-                    get_center_of_rect_for_text(&chunks[1], "Connect to Game"),
-                );
+                for (entry, chunk) in MAIN_MENU_ENTRIES.iter().zip(chunks.iter()) {
+                    frame.render_widget(simple_block.clone(), *chunk);
+                    frame.render_widget(
+                        Paragraph::new(*entry),
+                        get_center_of_rect_for_text(chunk, entry),
+                    );
+                }
 
                 if let Some(selected) = selected {
                     let selected_block = Block::default()
@@ -79,38 +447,128 @@ impl MenuState {
                         .border_type(tui::widgets::BorderType::Double)
                         .border_style(Style::default().fg(tui::style::Color::Green));
 
-                    frame.render_widget(selected_block, chunks[*selected as usize]);
+                    frame.render_widget(selected_block, chunks[*selected]);
                 }
             }
             MenuState::GameSettings {
+                mode,
                 selected,
                 amounts,
                 sizes,
+                variants,
+                conventions,
+                time_controls,
+                difficulties,
+                form,
+                ..
             } => {
+                let titles = Self::game_settings_titles(mode);
+
                 let chunks = Layout::default()
                     .direction(Direction::Horizontal)
                     .margin(2)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                    .constraints(
+                        titles
+                            .iter()
+                            .map(|_| Constraint::Ratio(1, titles.len() as u32))
+                            .collect::<Vec<_>>(),
+                    )
                     .split(frame.size());
 
                 let simple_block = Block::default().borders(Borders::ALL);
 
-                let titles = ["Pile Amount", "Pile Size"];
-
-                frame.render_widget(simple_block.clone().title(titles[0]), chunks[0]);
-                frame.render_widget(simple_block.clone().title(titles[1]), chunks[1]);
+                for (title, chunk) in titles.iter().zip(chunks.iter()) {
+                    frame.render_widget(simple_block.clone().title(*title), *chunk);
+                }
 
                 if let Some(selected) = selected {
                     let selected_block = Block::default()
                         .borders(Borders::ALL)
                         .border_type(tui::widgets::BorderType::Double)
                         .border_style(Style::default().fg(tui::style::Color::Green))
-                        .title(titles[*selected as usize]);
+                        .title(titles[*selected]);
+
+                    frame.render_widget(selected_block, chunks[*selected]);
+                }
+
+                let inner = |i: usize| {
+                    chunks[i].inner(&Margin {
+                        vertical: 1,
+                        horizontal: 1,
+                    })
+                };
 
-                    frame.render_widget(selected_block, chunks[*selected as usize]);
+                amounts.render(frame, inner(0));
+                sizes.render(frame, inner(1));
+                variants.render(frame, inner(2));
+                conventions.render(frame, inner(3));
+                time_controls.render(frame, inner(4));
+                if *mode == GameMode::Computer {
+                    difficulties.render(frame, inner(5));
                 }
 
-                amounts.render(
+                if let Some((_, form)) = form {
+                    form.render(frame);
+                }
+            }
+            MenuState::ConnectToPeer {
+                form,
+                games,
+                browser,
+            } => {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(2)
+                    .constraints([Constraint::Min(3), Constraint::Length(9)])
+                    .split(frame.size());
+
+                let (title, empty) = match browser {
+                    Some(_) => ("Games on your network", "Looking for games..."),
+                    None => (
+                        "Game discovery unavailable",
+                        "Enter the host's address below",
+                    ),
+                };
+                frame.render_widget(
+                    Block::default().borders(Borders::ALL).title(title),
+                    chunks[0],
+                );
+                let inner = chunks[0].inner(&Margin {
+                    vertical: 1,
+                    horizontal: 1,
+                });
+                if games.is_empty() {
+                    frame.render_widget(
+                        Paragraph::new(empty),
+                        get_center_of_rect_for_text(&inner, empty),
+                    );
+                } else {
+                    games.render(frame, inner);
+                }
+
+                form.render_in(frame, chunks[1]);
+            }
+            MenuState::WaitingForConnection { form, .. } => {
+                form.render(frame);
+            }
+            MenuState::Lobby {
+                form,
+                entries,
+                status,
+            } => {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(2)
+                    .constraints([Constraint::Min(3), Constraint::Length(9)])
+                    .split(frame.size());
+
+                frame.render_widget(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(status.as_str()),
+                    chunks[0],
+                );
+                entries.render(
                     frame,
                     chunks[0].inner(&Margin {
                         vertical: 1,
@@ -118,20 +576,27 @@ impl MenuState {
                     }),
                 );
 
-                sizes.render(
+                form.render_in(frame, chunks[1]);
+            }
+            MenuState::LoadGame { saves, status, .. } => {
+                let area = frame.size().inner(&Margin {
+                    vertical: 2,
+                    horizontal: 2,
+                });
+                frame.render_widget(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(status.as_str()),
+                    area,
+                );
+                saves.render(
                     frame,
-                    chunks[1].inner(&Margin {
+                    area.inner(&Margin {
                         vertical: 1,
                         horizontal: 1,
                     }),
                 );
             }
-            MenuState::ConnectToPeer { form, .. } => {
-                form.render(frame);
-            }
-            MenuState::WaitingForConnection { form, .. } => {
-                form.render(frame);
-            }
         }
     }
 
@@ -140,17 +605,23 @@ impl MenuState {
             MenuState::MainMenu { selected } => {
                 match key {
                     KeyCode::Left => {
-                        *selected = Some(false);
+                        *selected = Some(selected.map_or(0, |s| s.saturating_sub(1)));
                     }
                     KeyCode::Right => {
-                        *selected = Some(true);
+                        *selected =
+                            Some(selected.map_or(0, |s| (s + 1).min(MAIN_MENU_ENTRIES.len() - 1)));
                     }
                     KeyCode::Enter => match selected {
-                        Some(true) => {
+                        Some(0) => {
+                            *self = MenuState::game_settings(GameMode::Network);
+                        }
+                        Some(1) => {
+                            let mut games = StatefulList::with_items(Vec::new());
+                            games.unselect();
                             *self = MenuState::ConnectToPeer {
                                 form: StringForm::new(
-                                    "Connect to peer".into(),
-                                    20,
+                                    "Connect to peer (Tab to watch)".into(),
+                                    34,
                                     Some(
                                         local_ip()
                                             .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
@@ -158,26 +629,27 @@ impl MenuState {
                                             + ":",
                                     ),
                                 ),
-                                client: None,
+                                games,
+                                browser: Browser::start().await.ok(),
                             };
                         }
-                        Some(false) => {
-                            *self = MenuState::GameSettings {
-                                selected: None,
-                                amounts: StatefulList::with_items(vec![
-                                    PileAmount::Two,
-                                    PileAmount::Five,
-                                    PileAmount::Ten,
-                                ]),
-                                sizes: StatefulList::with_items(vec![
-                                    PileSize::Small,
-                                    PileSize::Medium,
-                                    PileSize::Large,
-                                ]),
-                            };
+                        Some(2) => {
+                            *self = MenuState::game_settings(GameMode::Computer);
+                        }
+                        Some(3) => {
+                            *self = MenuState::game_settings(GameMode::Local);
+                        }
+                        Some(4) => {
+                            *self = MenuState::load_game(Kind::Save);
+                        }
+                        Some(_) => {
+                            let addr = env::var("TERM_NIM_LOBBY").unwrap_or_else(|_| {
+                                format!("{}:{LOBBY_PORT}", Ipv4Addr::LOCALHOST)
+                            });
+                            *self = MenuState::lobby(addr);
                         }
                         None => {
-                            *selected = Some(false);
+                            *selected = Some(0);
                         }
                     },
                     _ => {}
@@ -186,56 +658,57 @@ impl MenuState {
                 MenuStateTransition::Continue
             }
             MenuState::GameSettings {
+                mode,
                 selected,
                 amounts,
                 sizes,
+                variants,
+                conventions,
+                time_controls,
+                difficulties,
+                form,
+                ..
             } => {
+                if let Some((_, form)) = form {
+                    if key == KeyCode::Enter {
+                        return self.submit_setup_form().await;
+                    }
+                    form.handle_key(key);
+                    return MenuStateTransition::Continue;
+                }
+
+                let columns = Self::game_settings_titles(mode).len();
                 match key {
                     KeyCode::Left => {
-                        *selected = Some(false);
+                        *selected = Some(selected.map_or(0, |s| s.saturating_sub(1)));
                     }
                     KeyCode::Right => {
-                        *selected = Some(true);
+                        *selected = Some(selected.map_or(0, |s| (s + 1).min(columns - 1)));
                     }
                     KeyCode::Up => match selected {
-                        Some(true) => {
-                            sizes.previous();
-                        }
-                        Some(false) => {
-                            amounts.previous();
-                        }
+                        Some(0) => amounts.previous(),
+                        Some(1) => sizes.previous(),
+                        Some(2) => variants.previous(),
+                        Some(3) => conventions.previous(),
+                        Some(4) => time_controls.previous(),
+                        Some(5) => difficulties.previous(),
                         _ => {}
                     },
                     KeyCode::Down => match selected {
-                        Some(true) => {
-                            sizes.next();
-                        }
-                        Some(false) => {
-                            amounts.next();
-                        }
+                        Some(0) => amounts.next(),
+                        Some(1) => sizes.next(),
+                        Some(2) => variants.next(),
+                        Some(3) => conventions.next(),
+                        Some(4) => time_controls.next(),
+                        Some(5) => difficulties.next(),
                         _ => {}
                     },
                     KeyCode::Enter => match selected {
                         Some(_) => {
-                            let default_addr = format!(
-                                "{}:4088",
-                                local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
-                            );
-                            *self = MenuState::WaitingForConnection {
-                                form: StringForm::new(
-                                    "IP to expose".into(),
-                                    default_addr.len() as u16,
-                                    Some(default_addr),
-                                ),
-                                game: GameState::new(
-                                    amounts.get_selected().unwrap(),
-                                    sizes.get_selected().unwrap(),
-                                    PlayerType::Host,
-                                ),
-                            };
+                            return self.continue_setup().await;
                         }
                         None => {
-                            *selected = Some(false);
+                            *selected = Some(0);
                         }
                     },
                     _ => {}
@@ -243,35 +716,32 @@ impl MenuState {
 
                 MenuStateTransition::Continue
             }
-            MenuState::ConnectToPeer { form, client } => {
-                if key == KeyCode::Enter {
-                    if let Some(clt) = client {
-                        let addr = form.consume();
-                        let server = Arc::new(Server::new(addr.clone(), Default::default()));
-                        let mut initial_state = clt.connect_to_game(addr).await.unwrap();
-                        initial_state.player_type = PlayerType::Guest;
-                        *server.current_game_state.lock().unwrap() = initial_state;
-                        let state = server.current_game_state.clone();
-                        tokio::spawn(server.clone().start());
-                        MenuStateTransition::ConnectedToPeer(server, state, clt.clone())
+            MenuState::ConnectToPeer { form, games, .. } => {
+                if matches!(key, KeyCode::Up | KeyCode::Down) {
+                    if key == KeyCode::Up {
+                        games.previous();
+                    } else {
+                        games.next();
+                    }
+                    if let Some(game) = games.get_selected() {
+                        form.state = game.addr.clone();
+                    }
+                    MenuStateTransition::Continue
+                } else if matches!(key, KeyCode::Enter | KeyCode::Tab) {
+                    let addr = form.consume();
+                    let join_as = if key == KeyCode::Enter {
+                        JoinAs::Player
                     } else {
-                        let addr = form.consume();
-                        let clt = Client::new(addr);
-                        if let Err(e) = clt.check_connection().await {
-                            form.state = format!("Failed to connect: {e:?}");
-                            return MenuStateTransition::Continue;
+                        JoinAs::Spectator
+                    };
+                    match client::connect(&addr, join_as, None).await {
+                        Ok((game, connection, seat)) => MenuStateTransition::NetworkGame(
+                            NetworkGame::remote(addr, join_as, game, connection, seat),
+                        ),
+                        Err(e) => {
+                            form.state = format!("Failed to join: {e}");
+                            MenuStateTransition::Continue
                         }
-                        *client = Some(clt);
-                        let default_addr = format!(
-                            "{}:4088",
-                            local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
-                        );
-                        *form = StringForm::new(
-                            "IP to expose".into(),
-                            default_addr.len() as u16,
-                            Some(default_addr),
-                        );
-                        MenuStateTransition::Continue
                     }
                 } else {
                     form.handle_key(key);
@@ -282,14 +752,98 @@ impl MenuState {
                 if key == KeyCode::Enter {
                     let addr = form.consume();
                     let game = Arc::new(Mutex::new(game.clone()));
-                    let server = Arc::new(Server::new(addr, game.clone()));
-                    tokio::spawn(server.clone().start());
-                    MenuStateTransition::GameOpen(server, game)
+                    let server = Arc::new(Server::new(addr, game));
+                    if let Err(e) = server.start() {
+                        form.state = format!("Failed to host: {e}");
+                        return MenuStateTransition::Continue;
+                    }
+                    server.announce();
+                    MenuStateTransition::NetworkGame(NetworkGame::host(server))
                 } else {
                     form.handle_key(key);
                     MenuStateTransition::Continue
                 }
             }
+            MenuState::Lobby {
+                form,
+                entries,
+                status,
+            } => {
+                let addr = form.consume();
+                match (key, entries.get_selected()) {
+                    (KeyCode::Up, _) => entries.previous(),
+                    (KeyCode::Down, _) => entries.next(),
+                    (KeyCode::Enter, Some(LobbyEntry::Refresh) | None) => {
+                        match client::list_games(&addr).await {
+                            Ok(games) => {
+                                *status = match games.len() {
+                                    0 => "No games in the lobby yet".into(),
+                                    1 => "1 game in the lobby".into(),
+                                    n => format!("{n} games in the lobby"),
+                                };
+                                let mut items = vec![LobbyEntry::Refresh, LobbyEntry::Create];
+                                items.extend(games.into_iter().map(LobbyEntry::Game));
+                                entries.set_items(items);
+                            }
+                            Err(e) => *status = format!("Failed to list the games: {e}"),
+                        }
+                    }
+                    (KeyCode::Enter, Some(LobbyEntry::Create)) => {
+                        *self = MenuState::game_settings(GameMode::Lobby(addr));
+                    }
+                    (KeyCode::Enter | KeyCode::Tab, Some(LobbyEntry::Game(game))) => {
+                        let id = game.id;
+                        let join_as = if key == KeyCode::Enter {
+                            JoinAs::Player
+                        } else {
+                            JoinAs::Spectator
+                        };
+                        match client::connect(&format!("{addr}/games/{id}"), join_as, None).await {
+                            Ok((game, connection, seat)) => {
+                                return MenuStateTransition::NetworkGame(NetworkGame::joined_lobby(
+                                    &addr, id, join_as, game, connection, seat,
+                                ))
+                            }
+                            Err(e) => *status = format!("Failed to join: {e}"),
+                        }
+                    }
+                    _ => form.handle_key(key),
+                }
+                MenuStateTransition::Continue
+            }
+            MenuState::LoadGame { kind, saves, .. } => {
+                match key {
+                    KeyCode::Up => saves.previous(),
+                    KeyCode::Down => saves.next(),
+                    KeyCode::Tab => {
+                        *self = MenuState::load_game(match kind {
+                            Kind::Save => Kind::Replay,
+                            Kind::Replay => Kind::Save,
+                        });
+                    }
+                    KeyCode::Enter => match (kind, saves.get_selected()) {
+                        (Kind::Save, Some(save)) => return resume(save.game.clone()),
+                        (Kind::Replay, Some(save)) => {
+                            return MenuStateTransition::Replay(save.game.clone())
+                        }
+                        (_, None) => {}
+                    },
+                    _ => {}
+                }
+                MenuStateTransition::Continue
+            }
         }
     }
 }
+
+/// Picks a saved game back up, against the computer if it was played against it.
+fn resume(game: GameState) -> MenuStateTransition {
+    if game.difficulty.is_some() {
+        MenuStateTransition::ComputerGame(game)
+    } else {
+        MenuStateTransition::LocalGame(GameState {
+            hot_seat: true,
+            ..game
+        })
+    }
+}
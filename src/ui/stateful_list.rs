@@ -20,7 +20,22 @@ impl<T: ToString> StatefulList<T> {
         StatefulList { state, items }
     }
 
+    /// Replaces the items, keeping the selected one selected if it is still there.
+    pub fn set_items(&mut self, items: Vec<T>)
+    where
+        T: PartialEq,
+    {
+        let selected = self
+            .get_selected()
+            .and_then(|selected| items.iter().position(|item| item == selected));
+        self.state.select(selected);
+        self.items = items;
+    }
+
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -35,6 +50,9 @@ impl<T: ToString> StatefulList<T> {
     }
 
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -48,6 +66,10 @@ impl<T: ToString> StatefulList<T> {
         self.state.select(Some(i));
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     pub fn unselect(&mut self) {
         self.state.select(None);
     }
@@ -1,23 +1,29 @@
-use tui::layout::Rect;
+use tui::layout::{Constraint, Direction, Layout, Rect};
 
 pub fn get_center_of_rect_for_text(rect: &Rect, message: &str) -> Rect {
-    let center_x = rect.width / 2;
     let center_y = rect.height / 2;
-    let x = rect.x + center_x;
-    let y = rect.y + center_y;
-    let width = message.len() as u16;
+    let width = (message.chars().count() as u16).min(rect.width);
     let height = 1;
-    Rect::new(x - (width + 1) / 2, y, width, height)
+    Rect::new(
+        rect.x + (rect.width - width) / 2,
+        rect.y + center_y,
+        width,
+        height,
+    )
 }
 
 pub fn get_center_of_rect_for_list(rect: &Rect, list: &[String]) -> Rect {
-    let center_x = rect.width / 2;
-    let center_y = rect.height / 2;
-    let x = rect.x + center_x;
-    let y = rect.y + center_y;
-    let width = list.iter().map(|s| s.len()).max().unwrap_or(0) as u16 + 5;
-    let height = list.len() as u16;
-    Rect::new(x - (width + 1) / 2 - 2, y - (height + 1) / 2, width, height)
+    let width =
+        (list.iter().map(|s| s.chars().count()).max().unwrap_or(0) as u16 + 5).min(rect.width);
+    let height = (list.len() as u16).min(rect.height);
+    Rect::new(
+        (rect.x + (rect.width - width) / 2)
+            .saturating_sub(2)
+            .max(rect.x),
+        rect.y + (rect.height - height) / 2,
+        width,
+        height,
+    )
 }
 
 pub fn get_center_of_rect_for_rect(
@@ -37,3 +43,87 @@ pub fn get_center_of_rect_for_rect(
         inner_rect_height.min(rect.y + rect.height),
     )
 }
+
+/// Wraps `lines` to the width of `rect`, keeping only the last rows that fit in it.
+pub fn get_last_rows_for_rect(rect: &Rect, lines: impl IntoIterator<Item = String>) -> String {
+    let width = rect.width.max(1) as usize;
+    let mut rows: Vec<String> = Vec::new();
+    for line in lines {
+        let chars: Vec<char> = line.chars().collect();
+        rows.extend(chars.chunks(width).map(|row| row.iter().collect()));
+    }
+    let shown = rows.len().saturating_sub(rect.height as usize);
+    rows[shown..].join("\n")
+}
+
+/// Flows `count` cells of at least `min_width` by `min_height` into rows filling `rect`.
+/// When they don't all fit, the rows are scrolled so the one holding cell `focus` is visible.
+/// Returns the index of the first visible cell and the rects of the visible cells.
+pub fn get_grid_for_cells(
+    rect: &Rect,
+    count: usize,
+    min_width: u16,
+    min_height: u16,
+    focus: usize,
+) -> (usize, Vec<Rect>) {
+    if count == 0 {
+        return (0, vec![]);
+    }
+
+    let columns = ((rect.width / min_width) as usize).clamp(1, count);
+    let rows = count.div_ceil(columns);
+    let visible_rows = ((rect.height / min_height) as usize).clamp(1, rows);
+    let first_row = (focus / columns)
+        .saturating_sub(visible_rows - 1)
+        .min(rows - visible_rows);
+    let first = first_row * columns;
+
+    let cells = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Ratio(1, visible_rows as u32);
+            visible_rows
+        ])
+        .split(*rect)
+        .iter()
+        .flat_map(|row| {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Ratio(1, columns as u32); columns])
+                .split(*row)
+        })
+        .take(count - first)
+        .collect();
+    (first, cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_filling_the_rect_stays_inside_it() {
+        let rect = Rect::new(0, 0, 17, 3);
+        let centered = get_center_of_rect_for_text(&rect, "1000 => 100 + 900");
+        assert_eq!(centered, Rect::new(0, 1, 17, 1));
+        let centered = get_center_of_rect_for_text(&rect, "a much longer message than fits");
+        assert_eq!(centered, Rect::new(0, 1, 17, 1));
+        assert_eq!(
+            get_center_of_rect_for_text(&rect, "3"),
+            Rect::new(8, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn list_filling_the_rect_stays_inside_it() {
+        let rect = Rect::new(0, 0, 9, 3);
+        let list: Vec<String> = ["Nim", "Wythoff", "Kayles", "Lasker's Nim"]
+            .iter()
+            .map(|item| item.to_string())
+            .collect();
+        assert_eq!(
+            get_center_of_rect_for_list(&rect, &list),
+            Rect::new(0, 0, 9, 3)
+        );
+    }
+}